@@ -0,0 +1,75 @@
+//! Benchmarks of arithmetic-heavy programs on both backends.
+//!
+//! Programs are parsed before each measurement, so only resolving, compiling and executing them
+//! is measured.
+//!
+//! The representation of values used by the virtual machine is selected with the `nan-boxing`
+//! feature. `benches/compare.sh` compares both representations, saving a baseline without the
+//! feature and comparing against it with the feature.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use rslox::environment::Environment;
+use rslox::{Backend, Options};
+
+const FIB: &str = "
+fun fib(n) {
+  if (n < 2) return n;
+  return fib(n - 1) + fib(n - 2);
+}
+var result = fib(20);
+";
+
+const LOOP: &str = "
+var sum = 0;
+for (var i = 0; i < 100000; i = i + 1) {
+  sum = sum + i * 2 - i / 3;
+}
+";
+
+const MANDELBROT: &str = "
+fun mandelbrot(size, iterations) {
+  var inside = 0;
+  for (var y = 0; y < size; y = y + 1) {
+    for (var x = 0; x < size; x = x + 1) {
+      var cr = 2 * x / size - 1.5;
+      var ci = 2 * y / size - 1;
+      var zr = 0;
+      var zi = 0;
+      var i = 0;
+      while (i < iterations and zr * zr + zi * zi <= 4) {
+        var t = zr * zr - zi * zi + cr;
+        zi = 2 * zr * zi + ci;
+        zr = t;
+        i = i + 1;
+      }
+      if (i == iterations) inside = inside + 1;
+    }
+  }
+  return inside;
+}
+var result = mandelbrot(32, 50);
+";
+
+fn arithmetic(c: &mut Criterion) {
+    for (name, source) in [("fib", FIB), ("loop", LOOP), ("mandelbrot", MANDELBROT)] {
+        let mut group = c.benchmark_group(name);
+        for (backend_name, backend) in [("tree", Backend::Tree), ("vm", Backend::Vm)] {
+            let options = Options {
+                backend,
+                ..Options::default()
+            };
+            group.bench_function(backend_name, |b| {
+                b.iter_batched(
+                    || (rslox::parse(source).unwrap(), Environment::new()),
+                    |(stmts, mut env)| rslox::run_stmts(stmts, source, &mut env, &options).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, arithmetic);
+criterion_main!(benches);
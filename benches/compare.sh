@@ -0,0 +1,12 @@
+#!/bin/sh
+# Compares the virtual machine with and without NaN boxing on the arithmetic benchmarks.
+#
+# The benchmarks are run without the `nan-boxing` feature and saved as the `tagged` baseline, then
+# run with the feature and compared against the baseline. Criterion reports the change of every
+# benchmark, and the HTML report is written to target/criterion/report/index.html.
+#
+# Extra arguments are passed to Criterion, such as a filter: benches/compare.sh vm
+set -e
+cd "$(dirname "$0")/.."
+cargo bench --bench arithmetic -- --save-baseline tagged "$@"
+cargo bench --bench arithmetic --features nan-boxing -- --baseline tagged "$@"
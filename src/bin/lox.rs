@@ -1,19 +1,54 @@
-use std::cmp::Ordering;
 use std::env;
 use std::process;
 
+use rslox::printer::AstFormat;
+use rslox::{Backend, ErrorFormat, OptLevel, Options};
+
+const USAGE: &str =
+    "Usage: rslox [--error-format=human|json] [--dump-ast[=json|sexpr]] [--no-exec] [--ast] [--backend=tree|vm] [-O0|-O1] [--disassemble] [--trace] [--gc-stats] [script]";
+
 fn main() {
-    let mut args = env::args();
-    let err = match args.len().cmp(&2) {
-        Ordering::Less => rslox::run_prompt(),
-        Ordering::Equal => rslox::run_file(args.nth(1).unwrap().as_str()),
-        Ordering::Greater => {
-            println!("Usage: rslox [script]");
-            process::exit(64); // EX_USAGE
+    let mut options = Options::default();
+    let mut script = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--error-format=human" => options.error_format = ErrorFormat::Human,
+            "--error-format=json" => options.error_format = ErrorFormat::Json,
+            "--dump-ast" | "--dump-ast=json" => options.dump_ast = Some(AstFormat::Json),
+            "--dump-ast=sexpr" => options.dump_ast = Some(AstFormat::Sexpr),
+            "--no-exec" => options.skip_execution = true,
+            "--ast" => options.ast_input = true,
+            "--backend=tree" => options.backend = Backend::Tree,
+            "--backend=vm" => options.backend = Backend::Vm,
+            "-O0" => options.opt_level = OptLevel::O0,
+            "-O1" => options.opt_level = OptLevel::O1,
+            "--disassemble" => options.disassemble = true,
+            // Only the virtual machine can be traced
+            "--trace" => {
+                options.backend = Backend::Vm;
+                options.trace = true;
+            }
+            "--gc-stats" => options.gc_stats = true,
+            _ if arg.starts_with('-') || script.is_some() => usage(),
+            _ => script = Some(arg),
         }
+    }
+    let status = match script {
+        Some(path) => rslox::run_file(&path, &options),
+        None => rslox::run_prompt(&options).map(|()| 0),
     };
-    if let Err(err) = err {
-        println!("Internal error: {err}");
-        process::exit(74); // EX_IOERR
+    match status {
+        Ok(0) => (),
+        Ok(status) => process::exit(status),
+        Err(err) => {
+            println!("Internal error: {err}");
+            process::exit(74); // EX_IOERR
+        }
     }
 }
+
+// Prints the usage of the interpreter and exits
+fn usage() -> ! {
+    println!("{USAGE}");
+    process::exit(64); // EX_USAGE
+}
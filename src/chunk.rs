@@ -0,0 +1,290 @@
+//! Lox bytecode.
+//!
+//! The [compiler](crate::compiler) translates every function of a program into a [`Chunk`] of
+//! bytecode, which is executed by the [virtual machine](crate::vm). A chunk is a flat sequence of
+//! instructions, each made of a one byte [`OpCode`] followed by its operands, and a pool of the
+//! constants the instructions refer to.
+//!
+//! Operands are unsigned 16-bit integers stored in big-endian order, except for the flag telling
+//! whether a captured variable is a local variable, which is a single byte.
+
+use std::rc::Rc;
+
+use crate::span::Span;
+use crate::symbol::Symbol;
+
+/// Bytecode instruction.
+///
+/// Instructions pop their operands from the value stack and push their result. The operands
+/// stored in the bytecode after the opcode are listed in parentheses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    /// Pushes a constant (constant index).
+    Constant,
+    /// Pushes `nil`.
+    Nil,
+    /// Pushes `true`.
+    True,
+    /// Pushes `false`.
+    False,
+    /// Discards the value on top of the stack.
+    Pop,
+    /// Pushes a local variable (stack slot relative to the frame).
+    GetLocal,
+    /// Assigns the value on top of the stack to a local variable (stack slot).
+    SetLocal,
+    /// Pushes a global variable (constant index of the name).
+    GetGlobal,
+    /// Pops a value and binds it to a global variable (constant index of the name).
+    DefineGlobal,
+    /// Assigns the value on top of the stack to an existing global variable (constant index of the
+    /// name).
+    SetGlobal,
+    /// Pushes a captured variable (index in the closure).
+    GetUpvalue,
+    /// Assigns the value on top of the stack to a captured variable (index in the closure).
+    SetUpvalue,
+    /// Pops an instance and pushes one of its properties (constant index of the name).
+    GetProperty,
+    /// Checks that the value on top of the stack is an instance whose fields can be set.
+    CheckFields,
+    /// Pops a value and an instance, sets a field of the instance and pushes the value back
+    /// (constant index of the name).
+    SetProperty,
+    /// Pops a superclass and an instance and pushes a method of the superclass bound to the
+    /// instance (constant index of the name).
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    /// Pops a value and prints it.
+    Print,
+    /// Jumps forward (distance in bytes).
+    Jump,
+    /// Jumps forward if the value on top of the stack is falsey, leaving it on the stack (distance
+    /// in bytes).
+    JumpIfFalse,
+    /// Jumps backward (distance in bytes).
+    Loop,
+    /// Calls the value below the arguments on top of the stack (number of arguments).
+    Call,
+    /// Pushes a new closure of a function (constant index of the function), followed by whether
+    /// each captured variable is a local variable of the enclosing function (one byte) and its
+    /// stack slot or index in the enclosing closure.
+    Closure,
+    /// Moves the local variable on top of the stack to the heap and pops it.
+    CloseUpvalue,
+    /// Returns from the current function with the value on top of the stack.
+    Return,
+    /// Pops methods and pushes a new class with them (constant index of the name, number of
+    /// methods).
+    Class,
+    /// Pops methods and pushes a new class with them, inheriting from the superclass below the
+    /// methods (constant index of the name, number of methods).
+    Subclass,
+    /// Checks that the value on top of the stack can be inherited from (constant index of the
+    /// name of the subclass).
+    Superclass,
+}
+
+/// Constant referred to by instructions.
+#[derive(Debug)]
+pub enum Constant {
+    Number(f64),
+    /// String literal or name of a variable, property or class.
+    String(Symbol),
+    Function(Rc<FunctionProto>),
+}
+
+/// Compiled function, which closures are created from at runtime.
+#[derive(Debug)]
+pub struct FunctionProto {
+    pub(crate) name: String,
+    pub(crate) arity: usize,
+    /// Number of variables captured from enclosing functions.
+    pub(crate) upvalues: usize,
+    pub(crate) chunk: Chunk,
+}
+
+/// Sequence of bytecode instructions with their constants.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub(crate) code: Vec<u8>,
+    /// Spans of source code the bytes were compiled from, as runs of consecutive bytes compiled
+    /// from the same span, each starting at the offset of its first byte.
+    spans: Vec<(usize, Span)>,
+    /// Spans of the callees of [`OpCode::Call`] instructions, which values that cannot be called
+    /// are reported at, by offset of the instruction.
+    callees: Vec<(usize, Span)>,
+    pub(crate) constants: Vec<Constant>,
+}
+
+impl OpCode {
+    // Opcodes in the order of their discriminants
+    const ALL: [OpCode; 39] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::CheckFields,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Subclass,
+        OpCode::Superclass,
+    ];
+
+    /// Decodes an opcode, `None` if the byte is not an opcode.
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OpCode::ALL
+            .get(usize::from(byte))
+            .copied()
+            .filter(|op| *op as u8 == byte)
+    }
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Chunk::default()
+    }
+
+    /// Appends a byte compiled from a span of source code.
+    pub fn write(&mut self, byte: u8, span: Span) {
+        if self.spans.last().map(|(_, last)| *last) != Some(span) {
+            self.spans.push((self.code.len(), span));
+        }
+        self.code.push(byte);
+    }
+
+    /// Appends a 16-bit operand.
+    pub fn write_u16(&mut self, value: u16, span: Span) {
+        for byte in value.to_be_bytes() {
+            self.write(byte, span);
+        }
+    }
+
+    /// Appends a call with a number of arguments, compiled from the span of the call expression and
+    /// the span of its callee.
+    pub fn write_call(&mut self, arguments: u16, span: Span, callee: Span) {
+        self.callees.push((self.code.len(), callee));
+        self.write(OpCode::Call as u8, span);
+        self.write_u16(arguments, span);
+    }
+
+    /// Returns the span of source code the byte at an offset was compiled from.
+    pub fn span(&self, offset: usize) -> Span {
+        let run = self.spans.partition_point(|(start, _)| *start <= offset);
+        self.spans[run - 1].1
+    }
+
+    /// Returns the span the last byte was compiled from, `None` if the chunk is empty.
+    pub fn last_span(&self) -> Option<Span> {
+        self.spans.last().map(|(_, span)| *span)
+    }
+
+    /// Returns the span of the callee of the call instruction at an offset.
+    pub fn callee_span(&self, offset: usize) -> Span {
+        let index = self
+            .callees
+            .binary_search_by_key(&offset, |(start, _)| *start)
+            .expect("Calls should have the span of their callee");
+        self.callees[index].1
+    }
+
+    /// Reads the 16-bit operand at an offset.
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    /// Overwrites the 16-bit operand at an offset, used to patch jumps once their target is known.
+    pub fn patch_u16(&mut self, offset: usize, value: u16) {
+        self.code[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Adds a constant to the pool, returning its index or `None` if the pool is full.
+    pub fn add_constant(&mut self, constant: Constant) -> Option<u16> {
+        let index = u16::try_from(self.constants.len()).ok()?;
+        self.constants.push(constant);
+        Some(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opcodes() {
+        for byte in 0..=OpCode::Superclass as u8 {
+            assert_eq!(OpCode::from_byte(byte).map(|op| op as u8), Some(byte));
+        }
+        assert_eq!(OpCode::from_byte(OpCode::Superclass as u8 + 1), None);
+    }
+
+    #[test]
+    fn operands() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Jump as u8, Span::new(0, 1));
+        chunk.write_u16(0, Span::new(0, 1));
+        chunk.patch_u16(1, 0x1234);
+        assert_eq!(chunk.code, [OpCode::Jump as u8, 0x12, 0x34]);
+        assert_eq!(chunk.read_u16(1), 0x1234);
+        assert_eq!(chunk.span(2), Span::new(0, 1));
+    }
+
+    #[test]
+    fn spans() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Nil as u8, Span::new(0, 3));
+        chunk.write_call(1, Span::new(4, 10), Span::new(4, 5));
+        chunk.write(OpCode::Return as u8, Span::new(4, 10));
+        chunk.write(OpCode::Pop as u8, Span::new(11, 12));
+        // Bytes compiled from the same span share a run
+        assert_eq!(chunk.spans.len(), 3);
+        assert_eq!(chunk.span(0), Span::new(0, 3));
+        assert_eq!(chunk.span(1), Span::new(4, 10));
+        assert_eq!(chunk.span(4), Span::new(4, 10));
+        assert_eq!(chunk.span(5), Span::new(11, 12));
+        assert_eq!(chunk.last_span(), Some(Span::new(11, 12)));
+        assert_eq!(chunk.callee_span(1), Span::new(4, 5));
+    }
+}
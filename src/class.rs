@@ -0,0 +1,187 @@
+//! Lox classes and instances.
+//!
+//! Classes are first-class Lox values created when a class declaration is executed.
+//! Calling a class creates a new instance of it, running the `init` method if the class has one.
+//! Classes can inherit methods from a single superclass.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::expression::LoxValue;
+use crate::function::LoxFunction;
+use crate::gc::Tracer;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::{Diagnostic, ErrorKind};
+
+/// Class value.
+pub struct LoxClass {
+    name: Symbol,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<Symbol, Rc<LoxFunction>>,
+}
+
+/// Class instance value.
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    fields: RefCell<HashMap<Symbol, LoxValue>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: Symbol,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<Symbol, Rc<LoxFunction>>,
+    ) -> Self {
+        LoxClass {
+            name,
+            superclass,
+            methods,
+        }
+    }
+
+    /// Returns the method with the given name, if the class or one of its superclasses defines it.
+    pub fn find_method(&self, name: &Symbol) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self.superclass.as_ref()?.find_method(name),
+        }
+    }
+
+    /// Returns the number of arguments the class takes when called, which is the arity of its
+    /// initializer.
+    pub fn arity(&self) -> usize {
+        self.find_method(&Symbol::init())
+            .map_or(0, |init| init.arity())
+    }
+
+    /// Creates a new instance of a class, calling the initializer with a list of arguments.
+    /// Returns the instance, [`Diagnostic`] is returned at the span of the call if there is an error.
+    pub fn call(
+        class: &Rc<LoxClass>,
+        arguments: Vec<LoxValue>,
+        env: &mut Environment,
+        span: Span,
+    ) -> Result<LoxValue, Diagnostic> {
+        let instance = Rc::new(LoxInstance::new(Rc::clone(class)));
+        env.heap_mut().register_instance(&instance);
+        let instance = LoxValue::Instance(instance);
+        match class.find_method(&Symbol::init()) {
+            Some(init) => init.bind(instance.clone()).call(arguments, env, span),
+            None if arguments.is_empty() => Ok(instance),
+            None => Err(class.arity_error(arguments.len(), span)),
+        }
+    }
+
+    pub(crate) fn trace(&self, tracer: &mut Tracer) {
+        if let Some(superclass) = &self.superclass {
+            tracer.value(&LoxValue::Class(Rc::clone(superclass)));
+        }
+        for method in self.methods.values() {
+            tracer.value(&LoxValue::Function(Rc::clone(method)));
+        }
+    }
+
+    /// Returns the error for a call to a class without an initializer with arguments.
+    pub(crate) fn arity_error(&self, arguments: usize, span: Span) -> Diagnostic {
+        Diagnostic::LoxError {
+            kind: ErrorKind::ArityMismatch,
+            span,
+            message: format!("class [{self}] expected 0 arguments but got {arguments}"),
+            notes: vec![format!(
+                "class [{self}] has no [init] method, so its instances are created without arguments"
+            )],
+        }
+    }
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        LoxInstance {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the value of a property of an instance, looking up fields before methods.
+    /// [`Diagnostic`] is returned at the span of the property access if the property is not
+    /// defined.
+    pub fn get(
+        instance: &Rc<LoxInstance>,
+        name: &Symbol,
+        span: Span,
+    ) -> Result<LoxValue, Diagnostic> {
+        if let Some(value) = instance.fields.borrow().get(name) {
+            return Ok(value.clone());
+        }
+        match instance.class.find_method(name) {
+            Some(method) => {
+                let method = method.bind(LoxValue::Instance(Rc::clone(instance)));
+                Ok(LoxValue::Function(Rc::new(method)))
+            }
+            None => Err(Diagnostic::LoxError {
+                kind: ErrorKind::UndefinedProperty,
+                span,
+                message: format!("undefined property [{name}] of instance [{instance}]"),
+                notes: Vec::new(),
+            }),
+        }
+    }
+
+    /// Sets the value of a field of an instance, creating the field if it does not exist.
+    pub fn set(&self, name: Symbol, value: LoxValue) {
+        self.fields.borrow_mut().insert(name, value);
+    }
+
+    pub(crate) fn trace(&self, tracer: &mut Tracer) {
+        tracer.value(&LoxValue::Class(Rc::clone(&self.class)));
+        for value in self.fields.borrow().values() {
+            tracer.value(value);
+        }
+    }
+
+    /// Removes every field of an instance, used by the collector to break cycles.
+    pub(crate) fn take_fields(&self) -> HashMap<Symbol, LoxValue> {
+        std::mem::take(&mut *self.fields.borrow_mut())
+    }
+}
+
+// Classes and instances are only equal to themselves
+impl PartialEq for LoxClass {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl PartialEq for LoxInstance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl fmt::Display for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<class {}>", self.name)
+    }
+}
+
+impl fmt::Display for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{} instance>", self.class.name)
+    }
+}
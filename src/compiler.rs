@@ -0,0 +1,628 @@
+//! Lox bytecode compiler.
+//!
+//! The compiler translates a resolved program into [bytecode](crate::chunk) for the
+//! [virtual machine](crate::vm), compiling each function declaration into its own chunk. Local
+//! variables are assigned stack slots at compile time and variables captured by closures are
+//! turned into upvalues, while globals are still looked up by name at runtime.
+//!
+//! Programs must be checked by the [resolver](crate::resolver) before they are compiled, since the
+//! compiler assumes they are valid.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::expression::{BinaryOp, Expr, LiteralValue, LogicalOp, UnaryOp};
+use crate::span::Span;
+use crate::statement::{Function, Stmt};
+use crate::symbol::Symbol;
+use crate::{Diagnostic, ErrorKind};
+
+/// Compiles a program into the function executed as its top-level code.
+///
+/// [`Diagnostic`] is returned if a function exceeds the limits of the bytecode format, such as
+/// having more than 65536 constants or jumping over more than 65535 bytes of code.
+pub fn compile(stmts: &[Stmt]) -> Result<Rc<FunctionProto>, Diagnostic> {
+    let mut compiler = Compiler {
+        functions: vec![FunctionState::new("script", FunctionKind::Script)],
+    };
+    for stmt in stmts {
+        compiler.compile_stmt(stmt)?;
+    }
+    let span = compiler.last_span();
+    compiler.emit_return(span);
+    let script = compiler.functions.pop().expect("Script should be compiled");
+    Ok(Rc::new(script.finish()))
+}
+
+// Kind of function being compiled
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+// Local variable stored in a stack slot of the function being compiled
+struct Local {
+    name: Symbol,
+    depth: usize,
+    captured: bool,
+}
+
+// Variable captured by the function being compiled, either a local variable of the enclosing
+// function or a variable the enclosing function captured itself
+#[derive(PartialEq)]
+struct Capture {
+    index: u16,
+    is_local: bool,
+}
+
+// Key of a constant which can be shared by several instructions of a chunk
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    String(Symbol),
+}
+
+// State of a function being compiled
+struct FunctionState {
+    name: String,
+    arity: usize,
+    kind: FunctionKind,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    captures: Vec<Capture>,
+    scope_depth: usize,
+    constants: HashMap<ConstantKey, u16>,
+}
+
+struct Compiler {
+    // Functions being compiled, from the script to the innermost function
+    functions: Vec<FunctionState>,
+}
+
+impl FunctionState {
+    fn new(name: &str, kind: FunctionKind) -> Self {
+        // The first slot holds the function being called, or the instance a method is bound to
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        FunctionState {
+            name: name.to_string(),
+            arity: 0,
+            kind,
+            chunk: Chunk::new(),
+            locals: vec![Local {
+                name: Symbol::intern(receiver),
+                depth: 0,
+                captured: false,
+            }],
+            captures: Vec::new(),
+            scope_depth: 0,
+            constants: HashMap::new(),
+        }
+    }
+
+    fn finish(self) -> FunctionProto {
+        FunctionProto {
+            name: self.name,
+            arity: self.arity,
+            upvalues: self.captures.len(),
+            chunk: self.chunk,
+        }
+    }
+
+    // Returns the slot of the innermost local variable with a name
+    fn resolve_local(&self, name: &Symbol) -> Option<u16> {
+        let slot = self.locals.iter().rposition(|local| local.name == *name)?;
+        Some(slot as u16)
+    }
+}
+
+impl Compiler {
+    fn compile_stmts(&mut self, stmts: &[Stmt]) -> Result<(), Diagnostic> {
+        for stmt in stmts {
+            self.compile_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn compile_stmt(&mut self, stmt: &Stmt) -> Result<(), Diagnostic> {
+        match stmt {
+            Stmt::Block(stmts) => {
+                self.begin_scope();
+                self.compile_stmts(stmts)?;
+                let span = self.last_span();
+                self.end_scope(span);
+            }
+            Stmt::Class(class) => {
+                let name = self.name_constant(&class.name, class.span)?;
+                // Local classes get their slot before the methods are compiled, so the methods can
+                // capture it
+                let slot = if self.current().scope_depth > 0 {
+                    self.emit_op(OpCode::Nil, class.span);
+                    Some(self.add_local(&class.name, class.span)?)
+                } else {
+                    None
+                };
+                if let Some(superclass) = &class.superclass {
+                    self.compile_expr(superclass)?;
+                    self.emit_op_u16(OpCode::Superclass, name, superclass.span());
+                    // Methods of subclasses capture the superclass as `super`
+                    self.begin_scope();
+                    self.add_local(&Symbol::super_(), superclass.span())?;
+                }
+                for method in &class.methods {
+                    let kind = if method.name == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
+                    self.compile_function(method, kind)?;
+                }
+                let methods = self.operand(class.methods.len(), class.span)?;
+                let op = match class.superclass {
+                    Some(_) => OpCode::Subclass,
+                    None => OpCode::Class,
+                };
+                self.emit_op_u16(op, name, class.span);
+                self.emit_u16(methods, class.span);
+                match slot {
+                    Some(slot) => {
+                        self.emit_op_u16(OpCode::SetLocal, slot, class.span);
+                        self.emit_op(OpCode::Pop, class.span);
+                    }
+                    None => self.emit_op_u16(OpCode::DefineGlobal, name, class.span),
+                }
+                if class.superclass.is_some() {
+                    self.end_scope(class.span);
+                }
+            }
+            Stmt::Expression(expr) => {
+                self.compile_expr(expr)?;
+                self.emit_op(OpCode::Pop, expr.span());
+            }
+            Stmt::Function(function) => {
+                // Local functions are declared before their body is compiled, so they can call
+                // themselves
+                if self.current().scope_depth > 0 {
+                    self.add_local(&function.name, function.span)?;
+                    self.compile_function(function, FunctionKind::Function)?;
+                } else {
+                    let name = self.name_constant(&function.name, function.span)?;
+                    self.compile_function(function, FunctionKind::Function)?;
+                    self.emit_op_u16(OpCode::DefineGlobal, name, function.span);
+                }
+            }
+            Stmt::If(stmt) => {
+                let span = stmt.condition.span();
+                self.compile_expr(&stmt.condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit_op(OpCode::Pop, span);
+                self.compile_stmt(&stmt.then_branch)?;
+                let else_jump = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(then_jump, span)?;
+                self.emit_op(OpCode::Pop, span);
+                if let Some(else_branch) = &stmt.else_branch {
+                    self.compile_stmt(else_branch)?;
+                }
+                self.patch_jump(else_jump, span)?;
+            }
+            Stmt::Print(expr) => {
+                self.compile_expr(expr)?;
+                self.emit_op(OpCode::Print, expr.span());
+            }
+            Stmt::Return(stmt) => match &stmt.value {
+                Some(value) => {
+                    self.compile_expr(value)?;
+                    self.emit_op(OpCode::Return, stmt.span);
+                }
+                None => self.emit_return(stmt.span),
+            },
+            Stmt::Var(stmt) => {
+                match &stmt.initializer {
+                    Some(initializer) => self.compile_expr(initializer)?,
+                    None => self.emit_op(OpCode::Nil, stmt.span),
+                }
+                // The value of a local variable stays on the stack as its slot
+                if self.current().scope_depth > 0 {
+                    self.add_local(&stmt.name, stmt.span)?;
+                } else {
+                    let name = self.name_constant(&stmt.name, stmt.span)?;
+                    self.emit_op_u16(OpCode::DefineGlobal, name, stmt.span);
+                }
+            }
+            Stmt::While(stmt) => {
+                let span = stmt.condition.span();
+                let loop_start = self.current().chunk.code.len();
+                self.compile_expr(&stmt.condition)?;
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit_op(OpCode::Pop, span);
+                self.compile_stmt(&stmt.body)?;
+                self.emit_loop(loop_start, span)?;
+                self.patch_jump(exit_jump, span)?;
+                self.emit_op(OpCode::Pop, span);
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> Result<(), Diagnostic> {
+        match expr {
+            Expr::Assign(expr) => {
+                self.compile_expr(&expr.value)?;
+                self.set_variable(&expr.name, expr.span)?;
+            }
+            Expr::Binary(expr) => {
+                self.compile_expr(&expr.left)?;
+                self.compile_expr(&expr.right)?;
+                let op = match expr.operator {
+                    BinaryOp::Less => OpCode::Less,
+                    BinaryOp::LessEqual => OpCode::LessEqual,
+                    BinaryOp::Greater => OpCode::Greater,
+                    BinaryOp::GreaterEqual => OpCode::GreaterEqual,
+                    BinaryOp::Equal => OpCode::Equal,
+                    BinaryOp::NotEqual => OpCode::NotEqual,
+                    BinaryOp::Mul => OpCode::Multiply,
+                    BinaryOp::Div => OpCode::Divide,
+                    BinaryOp::Add => OpCode::Add,
+                    BinaryOp::Sub => OpCode::Subtract,
+                };
+                self.emit_op(op, expr.operator_span);
+            }
+            Expr::Call(expr) => {
+                self.compile_expr(&expr.callee)?;
+                for argument in &expr.arguments {
+                    self.compile_expr(argument)?;
+                }
+                let arguments = self.operand(expr.arguments.len(), expr.span)?;
+                let callee = expr.callee.span();
+                self.current()
+                    .chunk
+                    .write_call(arguments, expr.span, callee);
+            }
+            Expr::Get(expr) => {
+                self.compile_expr(&expr.object)?;
+                let name = self.name_constant(&expr.name, expr.span)?;
+                self.emit_op_u16(OpCode::GetProperty, name, expr.span);
+            }
+            Expr::Gropuping(expr) => self.compile_expr(&expr.expression)?,
+            Expr::Literal(literal) => match &literal.value {
+                LiteralValue::Bool(true) => self.emit_op(OpCode::True, literal.span),
+                LiteralValue::Bool(false) => self.emit_op(OpCode::False, literal.span),
+                LiteralValue::Nil => self.emit_op(OpCode::Nil, literal.span),
+                LiteralValue::Number(value) => {
+                    let constant = self.constant(Constant::Number(*value), literal.span)?;
+                    self.emit_op_u16(OpCode::Constant, constant, literal.span);
+                }
+                LiteralValue::String(value) => {
+                    let constant = self.constant(Constant::String(value.clone()), literal.span)?;
+                    self.emit_op_u16(OpCode::Constant, constant, literal.span);
+                }
+            },
+            // The left operand is left on the stack as the result if it decides it
+            Expr::Logical(expr) => {
+                self.compile_expr(&expr.left)?;
+                match expr.operator {
+                    LogicalOp::And => {
+                        let end_jump = self.emit_jump(OpCode::JumpIfFalse, expr.span);
+                        self.emit_op(OpCode::Pop, expr.span);
+                        self.compile_expr(&expr.right)?;
+                        self.patch_jump(end_jump, expr.span)?;
+                    }
+                    LogicalOp::Or => {
+                        let else_jump = self.emit_jump(OpCode::JumpIfFalse, expr.span);
+                        let end_jump = self.emit_jump(OpCode::Jump, expr.span);
+                        self.patch_jump(else_jump, expr.span)?;
+                        self.emit_op(OpCode::Pop, expr.span);
+                        self.compile_expr(&expr.right)?;
+                        self.patch_jump(end_jump, expr.span)?;
+                    }
+                }
+            }
+            // The object is checked before the value is evaluated, like in the tree-walker
+            Expr::Set(expr) => {
+                self.compile_expr(&expr.object)?;
+                self.emit_op(OpCode::CheckFields, expr.span);
+                self.compile_expr(&expr.value)?;
+                let name = self.name_constant(&expr.name, expr.span)?;
+                self.emit_op_u16(OpCode::SetProperty, name, expr.span);
+            }
+            Expr::Super(expr) => {
+                self.get_variable(&Symbol::this(), expr.span)?;
+                self.get_variable(&Symbol::super_(), expr.span)?;
+                let name = self.name_constant(&expr.method, expr.span)?;
+                self.emit_op_u16(OpCode::GetSuper, name, expr.span);
+            }
+            Expr::This(expr) => self.get_variable(&Symbol::this(), expr.span)?,
+            Expr::Unary(expr) => {
+                self.compile_expr(&expr.operand)?;
+                let op = match expr.operator {
+                    UnaryOp::Not => OpCode::Not,
+                    UnaryOp::Neg => OpCode::Negate,
+                };
+                self.emit_op(op, expr.operator_span);
+            }
+            Expr::Variable(expr) => self.get_variable(&expr.name, expr.span)?,
+        }
+        Ok(())
+    }
+
+    // Compiles a function into its own chunk, emitting an instruction creating a closure of it
+    fn compile_function(
+        &mut self,
+        function: &Function,
+        kind: FunctionKind,
+    ) -> Result<(), Diagnostic> {
+        let mut state = FunctionState::new(&function.name, kind);
+        state.arity = function.params.len();
+        state.scope_depth = 1;
+        self.functions.push(state);
+        for param in &function.params {
+            self.add_local(param, function.span)?;
+        }
+        self.compile_stmts(&function.body)?;
+        let span = self.last_span();
+        self.emit_return(span);
+        let state = self.functions.pop().expect("Function should be compiled");
+        let captures = state
+            .captures
+            .iter()
+            .map(|capture| (capture.is_local, capture.index));
+        let captures: Vec<_> = captures.collect();
+        let proto = Constant::Function(Rc::new(state.finish()));
+        let constant = self.constant(proto, function.span)?;
+        self.emit_op_u16(OpCode::Closure, constant, function.span);
+        for (is_local, index) in captures {
+            self.emit_byte(u8::from(is_local), function.span);
+            self.emit_u16(index, function.span);
+        }
+        Ok(())
+    }
+
+    fn get_variable(&mut self, name: &Symbol, span: Span) -> Result<(), Diagnostic> {
+        let level = self.functions.len() - 1;
+        if let Some(slot) = self.functions[level].resolve_local(name) {
+            self.emit_op_u16(OpCode::GetLocal, slot, span);
+        } else if let Some(index) = self.resolve_capture(level, name, span)? {
+            self.emit_op_u16(OpCode::GetUpvalue, index, span);
+        } else {
+            let name = self.name_constant(name, span)?;
+            self.emit_op_u16(OpCode::GetGlobal, name, span);
+        }
+        Ok(())
+    }
+
+    fn set_variable(&mut self, name: &Symbol, span: Span) -> Result<(), Diagnostic> {
+        let level = self.functions.len() - 1;
+        if let Some(slot) = self.functions[level].resolve_local(name) {
+            self.emit_op_u16(OpCode::SetLocal, slot, span);
+        } else if let Some(index) = self.resolve_capture(level, name, span)? {
+            self.emit_op_u16(OpCode::SetUpvalue, index, span);
+        } else {
+            let name = self.name_constant(name, span)?;
+            self.emit_op_u16(OpCode::SetGlobal, name, span);
+        }
+        Ok(())
+    }
+
+    // Returns the index of the upvalue of the function at a level which captures a variable from
+    // the enclosing functions, `None` if the variable is global
+    fn resolve_capture(
+        &mut self,
+        level: usize,
+        name: &Symbol,
+        span: Span,
+    ) -> Result<Option<u16>, Diagnostic> {
+        if level == 0 {
+            return Ok(None);
+        }
+        if let Some(slot) = self.functions[level - 1].resolve_local(name) {
+            self.functions[level - 1].locals[usize::from(slot)].captured = true;
+            return self.add_capture(level, slot, true, span).map(Some);
+        }
+        match self.resolve_capture(level - 1, name, span)? {
+            Some(index) => self.add_capture(level, index, false, span).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_capture(
+        &mut self,
+        level: usize,
+        index: u16,
+        is_local: bool,
+        span: Span,
+    ) -> Result<u16, Diagnostic> {
+        let capture = Capture { index, is_local };
+        let captures = &mut self.functions[level].captures;
+        if let Some(index) = captures.iter().position(|existing| *existing == capture) {
+            return Ok(index as u16);
+        }
+        let index = too_large(
+            u16::try_from(captures.len()).ok(),
+            "captured variables",
+            span,
+        )?;
+        captures.push(capture);
+        Ok(index)
+    }
+
+    // Adds a local variable in the innermost scope, whose value is on top of the stack
+    fn add_local(&mut self, name: &Symbol, span: Span) -> Result<u16, Diagnostic> {
+        let state = self.current();
+        let slot = too_large(
+            u16::try_from(state.locals.len()).ok(),
+            "local variables",
+            span,
+        )?;
+        state.locals.push(Local {
+            name: name.clone(),
+            depth: state.scope_depth,
+            captured: false,
+        });
+        Ok(slot)
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    // Exits the innermost scope, discarding its local variables and moving the captured ones to the
+    // heap
+    fn end_scope(&mut self, span: Span) {
+        let state = self.current();
+        state.scope_depth -= 1;
+        while let Some(local) = state.locals.last() {
+            if local.depth <= state.scope_depth {
+                break;
+            }
+            let op = if local.captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            state.chunk.write(op as u8, span);
+            state.locals.pop();
+        }
+    }
+
+    fn constant(&mut self, constant: Constant, span: Span) -> Result<u16, Diagnostic> {
+        let key = match &constant {
+            Constant::Number(value) => Some(ConstantKey::Number(value.to_bits())),
+            Constant::String(value) => Some(ConstantKey::String(value.clone())),
+            Constant::Function(_) => None,
+        };
+        let state = self.current();
+        if let Some(index) = key.as_ref().and_then(|key| state.constants.get(key)) {
+            return Ok(*index);
+        }
+        let index = too_large(state.chunk.add_constant(constant), "constants", span)?;
+        if let Some(key) = key {
+            state.constants.insert(key, index);
+        }
+        Ok(index)
+    }
+
+    fn name_constant(&mut self, name: &Symbol, span: Span) -> Result<u16, Diagnostic> {
+        self.constant(Constant::String(name.clone()), span)
+    }
+
+    // Converts a count to an operand
+    fn operand(&self, count: usize, span: Span) -> Result<u16, Diagnostic> {
+        too_large(u16::try_from(count).ok(), "operands", span)
+    }
+
+    fn emit_byte(&mut self, byte: u8, span: Span) {
+        self.current().chunk.write(byte, span);
+    }
+
+    fn emit_u16(&mut self, value: u16, span: Span) {
+        self.current().chunk.write_u16(value, span);
+    }
+
+    fn emit_op(&mut self, op: OpCode, span: Span) {
+        self.emit_byte(op as u8, span);
+    }
+
+    fn emit_op_u16(&mut self, op: OpCode, operand: u16, span: Span) {
+        self.emit_op(op, span);
+        self.emit_u16(operand, span);
+    }
+
+    // Returns `nil` from the current function, or the instance from an initializer
+    fn emit_return(&mut self, span: Span) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit_op_u16(OpCode::GetLocal, 0, span);
+        } else {
+            self.emit_op(OpCode::Nil, span);
+        }
+        self.emit_op(OpCode::Return, span);
+    }
+
+    // Emits a jump with a placeholder distance, returning the offset of the distance
+    fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+        self.emit_op_u16(op, u16::MAX, span);
+        self.current().chunk.code.len() - 2
+    }
+
+    // Sets the distance of a jump to the end of the chunk
+    fn patch_jump(&mut self, offset: usize, span: Span) -> Result<(), Diagnostic> {
+        let chunk = &mut self.current().chunk;
+        let distance = chunk.code.len() - offset - 2;
+        let distance = too_large(u16::try_from(distance).ok(), "code to jump over", span)?;
+        chunk.patch_u16(offset, distance);
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, span: Span) -> Result<(), Diagnostic> {
+        self.emit_op(OpCode::Loop, span);
+        let distance = self.current().chunk.code.len() + 2 - loop_start;
+        let distance = too_large(u16::try_from(distance).ok(), "code in a loop", span)?;
+        self.emit_u16(distance, span);
+        Ok(())
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("A function should be being compiled")
+    }
+
+    // Span of the last instruction, given to instructions which do not come from a single node
+    fn last_span(&mut self) -> Span {
+        self.current().chunk.last_span().unwrap_or_default()
+    }
+}
+
+// Reports a limit of the bytecode format being exceeded if a value does not fit in an operand
+fn too_large(value: Option<u16>, what: &str, span: Span) -> Result<u16, Diagnostic> {
+    value.ok_or_else(|| Diagnostic::ResolveError {
+        kind: ErrorKind::ProgramTooLarge,
+        span,
+        message: format!("too many {what} in one function"),
+        labels: Vec::new(),
+        notes: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{peg_parser, resolver};
+
+    fn compile_source(source: &str) -> Rc<FunctionProto> {
+        let stmts = peg_parser::parse(source).unwrap();
+        resolver::resolve(&stmts).unwrap();
+        compile(&stmts).unwrap()
+    }
+
+    #[test]
+    fn globals_and_constants() {
+        let script = compile_source("var a = 1; a = a + 1;");
+        let chunk = &script.chunk;
+        // Constants are shared by the instructions using them
+        assert_eq!(chunk.constants.len(), 2);
+        assert_eq!(chunk.code[0], OpCode::Constant as u8);
+        assert_eq!(chunk.code[3], OpCode::DefineGlobal as u8);
+        assert_eq!(chunk.span(0), Span::new(8, 9));
+        assert_eq!(chunk.code.last(), Some(&(OpCode::Return as u8)));
+    }
+
+    #[test]
+    fn closures() {
+        let script = compile_source("fun f() { var a = 1; fun g() { return a; } return g; }");
+        let Constant::Function(f) = &script.chunk.constants[1] else {
+            panic!("expected a function constant");
+        };
+        assert_eq!((f.name.as_str(), f.arity, f.upvalues), ("f", 0, 0));
+        let Some(Constant::Function(g)) = f.chunk.constants.last() else {
+            panic!("expected a function constant");
+        };
+        assert_eq!((g.name.as_str(), g.upvalues), ("g", 1));
+        assert_eq!(g.chunk.code[0], OpCode::GetUpvalue as u8);
+    }
+}
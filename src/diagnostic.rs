@@ -0,0 +1,246 @@
+//! Lox diagnostics.
+//!
+//! Every error found while loading, resolving or executing a program is reported as a
+//! [`Diagnostic`]. Each diagnostic has an [`ErrorKind`] with a stable code, so tools can match on
+//! the kind of an error instead of its message, which may change between versions.
+
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+
+use crate::span::Span;
+
+/// Error reported while loading, resolving or executing a program.
+#[derive(Debug, PartialEq)]
+pub enum Diagnostic {
+    /// Error at runtime.
+    LoxError {
+        kind: ErrorKind,
+        span: Span,
+        message: String,
+        notes: Vec<String>,
+    },
+    /// Syntax error, such as source code which does not match the grammar or an assignment to an
+    /// expression which cannot be assigned to.
+    SyntaxError {
+        kind: ErrorKind,
+        span: Span,
+        message: String,
+        notes: Vec<String>,
+    },
+    /// Syntax tree which could not be loaded from JSON. The span refers to the JSON document.
+    AstError { span: Span, message: String },
+    /// Error found before the program is executed, by the resolver or the bytecode compiler.
+    ResolveError {
+        kind: ErrorKind,
+        span: Span,
+        message: String,
+        /// Other spans related to the error, such as an earlier declaration, with their message.
+        labels: Vec<(Span, String)>,
+        notes: Vec<String>,
+    },
+}
+
+/// Kind of a [`Diagnostic`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// Source code which does not match the grammar.
+    Syntax,
+    /// JSON document which is not a valid syntax tree.
+    InvalidAst,
+    /// Assignment to an expression which is not a variable or a property.
+    InvalidAssignment,
+    /// Local variable read in its own initializer.
+    OwnInitializer,
+    /// Local variable declared twice in the same scope.
+    Redeclaration,
+    /// Return statement outside of a function.
+    TopLevelReturn,
+    /// Value returned from an initializer.
+    InitializerReturn,
+    /// `this` used outside of a class.
+    InvalidThis,
+    /// `super` used outside of a subclass.
+    InvalidSuper,
+    /// Class inheriting from itself.
+    SelfInheritance,
+    /// Function exceeding the limits of the bytecode format.
+    ProgramTooLarge,
+    /// Variable read or assigned before it is defined.
+    UndefinedVariable,
+    /// Operator applied to values of the wrong type.
+    InvalidOperand,
+    /// Value called which is not a function or class.
+    NotCallable,
+    /// Function or class called with the wrong number of arguments.
+    ArityMismatch,
+    /// Property accessed which is not defined on an instance.
+    UndefinedProperty,
+    /// Property accessed on a value which is not an instance.
+    NotAnInstance,
+    /// Class inheriting from a value which is not a class.
+    InvalidSuperclass,
+    /// Function calls nested deeper than the maximum call depth.
+    StackOverflow,
+    /// Printed value which could not be written to the output.
+    OutputFailed,
+}
+
+/// Severity of a [`Diagnostic`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The program cannot be executed, or execution was aborted.
+    Error,
+}
+
+impl Diagnostic {
+    /// Returns the kind of the diagnostic.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Diagnostic::LoxError { kind, .. }
+            | Diagnostic::SyntaxError { kind, .. }
+            | Diagnostic::ResolveError { kind, .. } => *kind,
+            Diagnostic::AstError { .. } => ErrorKind::InvalidAst,
+        }
+    }
+
+    /// Returns the stable code of the kind of the diagnostic.
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+
+    /// Returns the severity of the diagnostic.
+    pub fn severity(&self) -> Severity {
+        self.kind().severity()
+    }
+
+    /// Returns the span of source code the diagnostic is reported at.
+    pub fn span(&self) -> Span {
+        match self {
+            Diagnostic::LoxError { span, .. }
+            | Diagnostic::SyntaxError { span, .. }
+            | Diagnostic::ResolveError { span, .. }
+            | Diagnostic::AstError { span, .. } => *span,
+        }
+    }
+
+    /// Returns the message of the diagnostic.
+    pub fn message(&self) -> String {
+        match self {
+            Diagnostic::LoxError { message, .. }
+            | Diagnostic::SyntaxError { message, .. }
+            | Diagnostic::ResolveError { message, .. }
+            | Diagnostic::AstError { message, .. } => message.clone(),
+        }
+    }
+
+    /// Returns the spans of source code related to the diagnostic other than its own span, with
+    /// the message explaining each of them.
+    pub fn labels(&self) -> &[(Span, String)] {
+        match self {
+            Diagnostic::ResolveError { labels, .. } => labels,
+            _ => &[],
+        }
+    }
+
+    /// Returns the notes explaining the diagnostic.
+    pub fn notes(&self) -> &[String] {
+        match self {
+            Diagnostic::LoxError { notes, .. }
+            | Diagnostic::SyntaxError { notes, .. }
+            | Diagnostic::ResolveError { notes, .. } => notes,
+            Diagnostic::AstError { .. } => &[],
+        }
+    }
+}
+
+impl ErrorKind {
+    /// Returns the code of the error kind, which never changes once assigned.
+    ///
+    /// Codes are grouped by the phase reporting them: `E00xx` for loading errors, `E01xx` for
+    /// errors found before execution and `E02xx` for runtime errors.
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::Syntax => "E0001",
+            ErrorKind::InvalidAst => "E0002",
+            ErrorKind::InvalidAssignment => "E0003",
+            ErrorKind::OwnInitializer => "E0101",
+            ErrorKind::Redeclaration => "E0102",
+            ErrorKind::TopLevelReturn => "E0103",
+            ErrorKind::InitializerReturn => "E0104",
+            ErrorKind::InvalidThis => "E0105",
+            ErrorKind::InvalidSuper => "E0106",
+            ErrorKind::SelfInheritance => "E0107",
+            ErrorKind::ProgramTooLarge => "E0108",
+            ErrorKind::UndefinedVariable => "E0201",
+            ErrorKind::InvalidOperand => "E0202",
+            ErrorKind::NotCallable => "E0203",
+            ErrorKind::ArityMismatch => "E0204",
+            ErrorKind::UndefinedProperty => "E0205",
+            ErrorKind::NotAnInstance => "E0206",
+            ErrorKind::InvalidSuperclass => "E0207",
+            ErrorKind::StackOverflow => "E0208",
+            ErrorKind::OutputFailed => "E0209",
+        }
+    }
+
+    /// Returns the severity of diagnostics of this kind. Every kind is currently an error.
+    pub fn severity(self) -> Severity {
+        Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: {}",
+            self.severity(),
+            self.code(),
+            self.message()
+        )
+    }
+}
+
+impl Error for Diagnostic {}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peg_parser;
+
+    #[test]
+    fn display() {
+        let diagnostic = Diagnostic::LoxError {
+            kind: ErrorKind::UndefinedVariable,
+            span: Span::new(6, 7),
+            message: "undefined variable [a]".to_string(),
+            notes: Vec::new(),
+        };
+        assert_eq!(
+            diagnostic.to_string(),
+            "error[E0201]: undefined variable [a]"
+        );
+        let Err(errors) = peg_parser::parse("var a = ;") else {
+            panic!("expected a parse error")
+        };
+        assert_eq!(errors[0].kind(), ErrorKind::Syntax);
+        assert_eq!(errors[0].span(), Span::new(8, 9));
+        assert_eq!(errors[0].to_string(), "error[E0001]: unexpected [;]");
+        assert!(errors[0].notes()[0].starts_with("expected one of"));
+        let Err(errors) = peg_parser::parse("print 1 +") else {
+            panic!("expected a parse error")
+        };
+        assert_eq!(errors[0].span(), Span::new(9, 9));
+        assert_eq!(errors[0].message(), "unexpected end of file");
+    }
+}
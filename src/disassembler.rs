@@ -0,0 +1,237 @@
+//! Bytecode disassembler.
+//!
+//! The disassembler prints [chunks](crate::chunk) of bytecode in a readable form, one instruction
+//! per line with its offset, the line of source code it was compiled from and its decoded
+//! operands. Lines are only printed when they change, and jumps print their target:
+//!
+//! ```text
+//! == script ==
+//! 0000    1 Constant           0 1
+//! 0003    | DefineGlobal       1 "a"
+//! 0006    2 GetGlobal          1 "a"
+//! 0009    | JumpIfFalse        8 -> 0020
+//! ```
+//!
+//! The chunks of nested functions are printed after the chunk of the function declaring them.
+
+use std::fmt::{self, Write};
+
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::expression::LoxValue;
+use crate::span::LineIndex;
+
+/// Returns the bytecode of a compiled script and every function in it, with line numbers from the
+/// source code it was compiled from.
+pub fn disassemble(script: &FunctionProto, source: &str) -> String {
+    let mut out = String::new();
+    write_function(&mut out, script, &LineIndex::new(source))
+        .expect("Writing to a string should not fail");
+    out
+}
+
+/// Returns the instruction of a chunk at an offset, always with its line number. Closures take
+/// one more line per captured variable.
+pub fn instruction(chunk: &Chunk, offset: usize, lines: &LineIndex) -> String {
+    let mut out = String::new();
+    let line = line_number(lines.line(chunk.span(offset).start));
+    write_instruction(&mut out, chunk, offset, &line).expect("Writing to a string should not fail");
+    out.truncate(out.trim_end().len());
+    out
+}
+
+/// Returns the values on a stack from bottom to top, as printed before each traced instruction.
+pub fn stack(values: &[LoxValue]) -> String {
+    let mut out = " ".repeat(10);
+    for value in values {
+        match value {
+            LoxValue::String(string) => write!(out, "[ {string:?} ]"),
+            value => write!(out, "[ {value} ]"),
+        }
+        .expect("Writing to a string should not fail");
+    }
+    out
+}
+
+fn write_function(out: &mut String, function: &FunctionProto, lines: &LineIndex) -> fmt::Result {
+    writeln!(out, "== {} ==", function.name)?;
+    let chunk = &function.chunk;
+    let mut offset = 0;
+    let mut previous_line = None;
+    while offset < chunk.code.len() {
+        let line = lines.line(chunk.span(offset).start);
+        let line_column = if offset > 0 && line == previous_line {
+            "   |".to_string()
+        } else {
+            line_number(line)
+        };
+        previous_line = line;
+        offset = write_instruction(out, chunk, offset, &line_column)?;
+    }
+    for constant in &chunk.constants {
+        if let Constant::Function(function) = constant {
+            writeln!(out)?;
+            write_function(out, function, lines)?;
+        }
+    }
+    Ok(())
+}
+
+// Writes the instruction at an offset on its own line, returning the offset of the next one
+fn write_instruction(
+    out: &mut String,
+    chunk: &Chunk,
+    offset: usize,
+    line: &str,
+) -> Result<usize, fmt::Error> {
+    let Some(op) = OpCode::from_byte(chunk.code[offset]) else {
+        writeln!(out, "{offset:04} {line} Unknown({})", chunk.code[offset])?;
+        return Ok(offset + 1);
+    };
+    let mut text = format!("{offset:04} {line} {:<16}", format!("{op:?}"));
+    let operand = chunk
+        .code
+        .get(offset + 1..offset + 3)
+        .map(|_| chunk.read_u16(offset + 1));
+    let operand = usize::from(operand.unwrap_or_default());
+    let mut next = offset + 3;
+    match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Superclass => {
+            write!(text, "{operand:>4} {}", constant(chunk, operand))?;
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => write!(text, "{operand:>4}")?,
+        OpCode::Jump | OpCode::JumpIfFalse => {
+            write!(text, "{operand:>4} -> {:04}", offset + 3 + operand)?;
+        }
+        OpCode::Loop => write!(
+            text,
+            "{operand:>4} -> {:04}",
+            (offset + 3).saturating_sub(operand)
+        )?,
+        OpCode::Class | OpCode::Subclass => {
+            let methods = chunk.read_u16(offset + 3);
+            write!(
+                text,
+                "{operand:>4} {} ({methods} methods)",
+                constant(chunk, operand)
+            )?;
+            next = offset + 5;
+        }
+        OpCode::Closure => {
+            write!(text, "{operand:>4} {}", constant(chunk, operand))?;
+            let captures = match &chunk.constants[operand] {
+                Constant::Function(function) => function.upvalues,
+                _ => 0,
+            };
+            for _ in 0..captures {
+                let kind = match chunk.code[next] {
+                    1 => "local",
+                    _ => "upvalue",
+                };
+                let index = chunk.read_u16(next + 1);
+                write!(text, "\n{next:04}    | {:<16}{kind} {index}", "")?;
+                next += 3;
+            }
+        }
+        OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::Pop
+        | OpCode::CheckFields
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::Print
+        | OpCode::CloseUpvalue
+        | OpCode::Return => next = offset + 1,
+    }
+    writeln!(out, "{}", text.trim_end())?;
+    Ok(next)
+}
+
+// Returns a constant as it is written in source code
+fn constant(chunk: &Chunk, index: usize) -> String {
+    match chunk.constants.get(index) {
+        Some(Constant::Number(value)) => value.to_string(),
+        Some(Constant::String(value)) => format!("{value:?}"),
+        Some(Constant::Function(function)) => format!("<fn {}>", function.name),
+        None => "<invalid constant>".to_string(),
+    }
+}
+
+fn line_number(line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{line:>4}"),
+        None => "   -".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, peg_parser};
+
+    fn disassemble_source(source: &str) -> String {
+        let stmts = peg_parser::parse(source).unwrap();
+        disassemble(&compiler::compile(&stmts).unwrap(), source)
+    }
+
+    #[test]
+    fn script() {
+        assert_eq!(
+            disassemble_source("var a = 1;\nif (a) print \"b\";"),
+            "== script ==
+0000    1 Constant           0 1
+0003    | DefineGlobal       1 \"a\"
+0006    2 GetGlobal          1 \"a\"
+0009    | JumpIfFalse        8 -> 0020
+0012    | Pop
+0013    | Constant           2 \"b\"
+0016    | Print
+0017    | Jump               1 -> 0021
+0020    | Pop
+0021    | Nil
+0022    | Return
+"
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let source = "fun f(x) {\n  fun g() { return x; }\n}";
+        let listing = disassemble_source(source);
+        assert!(listing.contains(
+            "0000    2 Closure            0 <fn g>\n0003    |                 local 1\n"
+        ));
+        assert!(listing.contains("== g ==\n0000    2 GetUpvalue         0\n"));
+    }
+
+    #[test]
+    fn trace() {
+        let stmts = peg_parser::parse("print -1;").unwrap();
+        let script = compiler::compile(&stmts).unwrap();
+        let lines = LineIndex::new("");
+        assert_eq!(instruction(&script.chunk, 3, &lines), "0003    - Negate");
+        let values = [LoxValue::Number(1.0), LoxValue::String("a".into())];
+        assert_eq!(stack(&values), "          [ 1 ][ \"a\" ]");
+    }
+}
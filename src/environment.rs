@@ -0,0 +1,430 @@
+//! Lox runtime environment.
+//!
+//! The environment stores the values bound to variable names while a program is executing.
+//! Bindings are kept in a chain of scopes, starting from the innermost scope and ending at the
+//! global scope, where inner scopes shadow the scopes enclosing them.
+//!
+//! Scopes are shared and reference-counted, so a function can capture the scope it was declared in
+//! and keep using it after the scope has been exited.
+//!
+//! Local variables are looked up at the scope depth computed by the [resolver](crate::resolver),
+//! while unresolved variables are looked up in the global scope.
+//!
+//! The environment also owns the [heap](crate::gc) of objects managed by the garbage collector,
+//! whose roots are the global scope, the scopes of the calls executing and the values held while
+//! evaluating expressions.
+//!
+//! Values printed by the program are written to the output of the environment, which is the
+//! standard output unless another writer is given with [`Environment::with_output`].
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::expression::LoxValue;
+use crate::gc::{GcStats, Heap, Tracer};
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::{Diagnostic, ErrorKind};
+
+/// Maximum number of nested function calls before execution is aborted.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Native stack, in bytes, which must remain before a function call is executed.
+///
+/// The tree-walking interpreter nests Rust calls for every Lox call, using up to a few kilobytes of
+/// the native stack per call in debug builds. Calls which would leave less than this much stack
+/// continue on a new segment of [`STACK_SEGMENT`] bytes allocated on the heap, so the maximum call
+/// depth can be reached on threads of any size.
+pub const RED_ZONE: usize = 256 * 1024;
+
+/// Size of the segments of native stack allocated once [`RED_ZONE`] is reached.
+pub const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+/// Shared reference to a scope.
+pub type ScopeRef = Rc<RefCell<Scope>>;
+
+/// Returns the error for a call nested deeper than [`MAX_CALL_DEPTH`], reported at the span of the
+/// call.
+pub(crate) fn stack_overflow(span: Span) -> Diagnostic {
+    Diagnostic::LoxError {
+        kind: ErrorKind::StackOverflow,
+        span,
+        message: "stack overflow".to_string(),
+        notes: Vec::new(),
+    }
+}
+
+/// Returns the error for output which could not be written, reported at the span of the value
+/// printed.
+pub(crate) fn output_failed(err: &io::Error, span: Span) -> Diagnostic {
+    Diagnostic::LoxError {
+        kind: ErrorKind::OutputFailed,
+        span,
+        message: format!("cannot write output: {err}"),
+        notes: Vec::new(),
+    }
+}
+
+/// Variable bindings of a single scope, linked to the scope enclosing it.
+pub struct Scope {
+    values: HashMap<Symbol, LoxValue>,
+    enclosing: Option<ScopeRef>,
+}
+
+/// Variable bindings shared by statements and expressions during execution.
+pub struct Environment {
+    // Innermost scope of the chain currently executing
+    scope: ScopeRef,
+    globals: ScopeRef,
+    // Scopes of the callers of the calls executing, from the outermost call
+    callers: Vec<ScopeRef>,
+    // Frames of the virtual machines waiting for a call to a function of the tree-walking
+    // interpreter, which count towards the call depth
+    suspended_frames: usize,
+    // Values held by the interpreter while it evaluates other expressions
+    temporaries: Vec<LoxValue>,
+    heap: Heap,
+    output: Box<dyn Write>,
+}
+
+impl Scope {
+    fn new(enclosing: Option<ScopeRef>) -> ScopeRef {
+        Rc::new(RefCell::new(Scope {
+            values: HashMap::new(),
+            enclosing,
+        }))
+    }
+
+    /// Creates a new scope enclosed by another scope.
+    pub fn enclosed_by(enclosing: &ScopeRef) -> ScopeRef {
+        Scope::new(Some(Rc::clone(enclosing)))
+    }
+
+    /// Binds a value to a variable name in this scope.
+    pub fn define(&mut self, name: Symbol, value: LoxValue) {
+        self.values.insert(name, value);
+    }
+
+    /// Returns the value bound to a variable name in this scope, ignoring enclosing scopes.
+    pub fn get(&self, name: &Symbol) -> Option<LoxValue> {
+        self.values.get(name).cloned()
+    }
+
+    pub(crate) fn trace(&self, tracer: &mut Tracer) {
+        for value in self.values.values() {
+            tracer.value(value);
+        }
+        if let Some(enclosing) = &self.enclosing {
+            tracer.scope(enclosing);
+        }
+    }
+
+    /// Removes every binding of this scope, used by the collector to break cycles.
+    pub(crate) fn take_values(&mut self) -> HashMap<Symbol, LoxValue> {
+        std::mem::take(&mut self.values)
+    }
+}
+
+impl Environment {
+    /// Creates an environment printing to the standard output.
+    pub fn new() -> Self {
+        Environment::with_output(io::stdout())
+    }
+
+    /// Creates an environment printing to a writer instead of the standard output.
+    pub fn with_output(output: impl Write + 'static) -> Self {
+        let globals = Scope::new(None);
+        Environment {
+            scope: Rc::clone(&globals),
+            globals,
+            callers: Vec::new(),
+            suspended_frames: 0,
+            temporaries: Vec::new(),
+            heap: Heap::new(),
+            output: Box::new(output),
+        }
+    }
+
+    /// Returns the writer values are printed to.
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
+    }
+
+    /// Prints a value on its own line, returning [`ErrorKind::OutputFailed`] reported at the span
+    /// if the output cannot be written.
+    pub fn print(&mut self, value: &LoxValue, span: Span) -> Result<(), Diagnostic> {
+        writeln!(self.output, "{value}").map_err(|err| output_failed(&err, span))
+    }
+
+    /// Returns the heap of objects managed by the garbage collector.
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// Returns the heap mutably, to configure the collector or register new objects.
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// Returns the allocation and collection statistics of the garbage collector.
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// Holds a value being evaluated as a root of the garbage collector until it is released,
+    /// returning the number of values held before it.
+    pub(crate) fn hold(&mut self, value: &LoxValue) -> usize {
+        let mark = self.temporaries.len();
+        if matches!(
+            value,
+            LoxValue::Function(_) | LoxValue::Class(_) | LoxValue::Instance(_)
+        ) {
+            self.temporaries.push(value.clone());
+        }
+        mark
+    }
+
+    /// Releases the values held since [`Environment::hold`] returned a mark.
+    pub(crate) fn release(&mut self, mark: usize) {
+        self.temporaries.truncate(mark);
+    }
+
+    /// Collects garbage if enough objects were allocated since the last collection. The
+    /// interpreter must not hold values outside of the environment, except with
+    /// [`Environment::hold`].
+    pub fn collect_if_needed(&mut self) {
+        if self.heap.should_collect() {
+            self.collect_garbage(|_| ());
+        }
+    }
+
+    /// Collects garbage, with roots traced by a function in addition to the roots of the
+    /// environment.
+    pub(crate) fn collect_garbage(&mut self, trace_roots: impl FnOnce(&mut Tracer)) {
+        let mut tracer = Tracer::new();
+        tracer.scope(&self.globals);
+        tracer.scope(&self.scope);
+        for caller in &self.callers {
+            tracer.scope(caller);
+        }
+        for value in &self.temporaries {
+            tracer.value(value);
+        }
+        trace_roots(&mut tracer);
+        self.heap.sweep(tracer);
+    }
+
+    /// Returns a shared reference to the innermost scope, to be captured by a closure.
+    pub fn capture(&self) -> ScopeRef {
+        Rc::clone(&self.scope)
+    }
+
+    /// Enters a function call, entering a fresh scope for the call enclosed by the scope captured
+    /// by the function. The scope of the caller is saved until it is restored by
+    /// [`Environment::exit_call`] once the call completes.
+    ///
+    /// [`Diagnostic`] is returned at the span of the call if the maximum call depth is exceeded.
+    pub fn enter_call(&mut self, closure: &ScopeRef, span: Span) -> Result<(), Diagnostic> {
+        if self.call_depth() >= MAX_CALL_DEPTH {
+            return Err(stack_overflow(span));
+        }
+        let scope = Scope::enclosed_by(closure);
+        self.heap.register_scope(&scope);
+        self.callers.push(std::mem::replace(&mut self.scope, scope));
+        Ok(())
+    }
+
+    /// Exits the innermost function call, restoring the scope of its caller.
+    pub fn exit_call(&mut self) {
+        self.scope = self
+            .callers
+            .pop()
+            .expect("Calls should be exited after being entered");
+    }
+
+    /// Returns the number of calls executing, including the frames of the virtual machines which
+    /// called into the tree-walking interpreter.
+    pub(crate) fn call_depth(&self) -> usize {
+        self.callers.len() + self.suspended_frames
+    }
+
+    /// Counts the frames of a virtual machine towards the call depth while it waits for a call
+    /// executed by the tree-walking interpreter, until they are resumed.
+    pub(crate) fn suspend_frames(&mut self, frames: usize) {
+        self.suspended_frames += frames;
+    }
+
+    /// Stops counting frames suspended with [`Environment::suspend_frames`].
+    pub(crate) fn resume_frames(&mut self, frames: usize) {
+        self.suspended_frames -= frames;
+    }
+
+    /// Enters a new innermost scope.
+    pub fn push_scope(&mut self) {
+        self.scope = Scope::enclosed_by(&self.scope);
+        self.heap.register_scope(&self.scope);
+    }
+
+    /// Exits the innermost scope. Its bindings are discarded unless the scope was captured.
+    ///
+    /// The global scope is never exited.
+    pub fn pop_scope(&mut self) {
+        let enclosing = self.scope.borrow().enclosing.clone();
+        if let Some(enclosing) = enclosing {
+            self.scope = enclosing;
+        }
+    }
+
+    /// Binds a value to a variable name in the innermost scope, redefining the variable if it
+    /// already exists in that scope.
+    pub fn define(&mut self, name: Symbol, value: LoxValue) {
+        self.scope.borrow_mut().values.insert(name, value);
+    }
+
+    /// Returns the value bound to a variable name in the scope a number of scopes above the
+    /// innermost scope, `None` if the variable is not defined in that scope.
+    pub fn get_at(&self, depth: usize, name: &Symbol) -> Option<LoxValue> {
+        self.ancestor(depth).borrow().get(name)
+    }
+
+    /// Binds a value to a variable name in the global scope, redefining the variable if it already
+    /// exists.
+    pub fn define_global(&mut self, name: Symbol, value: LoxValue) {
+        self.globals.borrow_mut().values.insert(name, value);
+    }
+
+    /// Returns the value bound to a variable name in the global scope, `None` if the variable is
+    /// not defined.
+    pub fn get_global(&self, name: &Symbol) -> Option<LoxValue> {
+        self.globals.borrow().get(name)
+    }
+
+    /// Rebinds an existing variable in the scope a number of scopes above the innermost scope to a
+    /// new value, returning whether the variable is defined in that scope.
+    pub fn assign_at(&mut self, depth: usize, name: &Symbol, value: LoxValue) -> bool {
+        match self.ancestor(depth).borrow_mut().values.get_mut(name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Rebinds an existing variable in the global scope to a new value, returning whether the
+    /// variable is defined.
+    pub fn assign_global(&mut self, name: &Symbol, value: LoxValue) -> bool {
+        match self.globals.borrow_mut().values.get_mut(name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Returns the scope a number of scopes above the innermost scope
+    fn ancestor(&self, depth: usize) -> ScopeRef {
+        let mut scope = Rc::clone(&self.scope);
+        for _ in 0..depth {
+            let enclosing = scope
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("Resolved scope depth should not exceed the scope chain");
+            scope = enclosing;
+        }
+        scope
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn define_and_get() {
+        let mut env = Environment::new();
+        env.define("a".into(), LoxValue::Number(1.0));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Number(1.0)));
+        env.define("a".into(), LoxValue::Nil);
+        assert_eq!(env.get_at(0, &"a".into()), Some(LoxValue::Nil));
+    }
+
+    #[test]
+    fn assign() {
+        let mut env = Environment::new();
+        env.define("a".into(), LoxValue::Nil);
+        assert!(env.assign_global(&"a".into(), LoxValue::Bool(true)));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Bool(true)));
+    }
+
+    #[test]
+    fn undefined() {
+        let mut env = Environment::new();
+        assert!(env.get_global(&"a".into()).is_none());
+        assert!(!env.assign_global(&"a".into(), LoxValue::Nil));
+    }
+
+    #[test]
+    fn scopes() {
+        let mut env = Environment::new();
+        env.define("a".into(), LoxValue::Number(1.0));
+        env.define("b".into(), LoxValue::Number(1.0));
+        env.push_scope();
+        env.define("a".into(), LoxValue::Number(2.0));
+        env.define("c".into(), LoxValue::Number(2.0));
+        assert!(env.assign_global(&"b".into(), LoxValue::Number(3.0)));
+        assert_eq!(env.get_at(0, &"a".into()), Some(LoxValue::Number(2.0)));
+        assert!(env.get_at(0, &"b".into()).is_none());
+        env.pop_scope();
+        assert_eq!(env.get_at(0, &"a".into()), Some(LoxValue::Number(1.0)));
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Number(3.0)));
+        assert!(env.get_global(&"c".into()).is_none());
+    }
+
+    #[test]
+    fn calls() {
+        let mut env = Environment::new();
+        env.define("a".into(), LoxValue::Number(1.0));
+        env.push_scope();
+        env.define("b".into(), LoxValue::Number(1.0));
+        let closure = env.capture();
+        env.pop_scope();
+        env.push_scope();
+        env.define("c".into(), LoxValue::Number(1.0));
+        env.enter_call(&closure, Span::default()).unwrap();
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Number(1.0)));
+        assert_eq!(env.get_at(1, &"b".into()), Some(LoxValue::Number(1.0)));
+        assert!(env.get_at(2, &"c".into()).is_none());
+        env.define("d".into(), LoxValue::Number(2.0));
+        env.exit_call();
+        assert_eq!(env.get_at(0, &"c".into()), Some(LoxValue::Number(1.0)));
+        assert!(env.get_at(0, &"d".into()).is_none());
+    }
+
+    #[test]
+    fn resolved() {
+        let mut env = Environment::new();
+        env.define("a".into(), LoxValue::Number(1.0));
+        env.push_scope();
+        env.define("a".into(), LoxValue::Number(2.0));
+        env.push_scope();
+        assert_eq!(env.get_at(1, &"a".into()), Some(LoxValue::Number(2.0)));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Number(1.0)));
+        assert!(env.get_at(0, &"a".into()).is_none());
+        assert!(env.assign_at(1, &"a".into(), LoxValue::Nil));
+        assert!(env.assign_global(&"a".into(), LoxValue::Bool(true)));
+        env.pop_scope();
+        assert_eq!(env.get_at(0, &"a".into()), Some(LoxValue::Nil));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Bool(true)));
+    }
+}
@@ -3,46 +3,76 @@
 //! Lox expressions are modelled in a tree structure as a [`Expr`] type.
 //! They can be evaluated using [`Expr::eval`], returning a [`LoxValue`] type.
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
 use std::fmt;
-
-use super::Diagnostic;
+use std::rc::Rc;
+
+use super::class::{LoxClass, LoxInstance};
+use super::environment::Environment;
+use super::function::LoxFunction;
+use super::span::Span;
+use super::symbol::{LoxString, Symbol};
+use super::{Diagnostic, ErrorKind};
 // TODO: Fix proper visibility and imports for modules
 
 /// Expression types.
-#[derive(Serialize)]
+///
+/// Each node type is serialized with a versioned name, such as `Binary/v1`.
+#[derive(Deserialize, Serialize)]
 pub enum Expr {
+    #[serde(rename = "Assign/v1")]
     Assign(Assign),
+    #[serde(rename = "Binary/v1")]
     Binary(Binary),
-    // Call(Call),
-    // Get(Get),
+    #[serde(rename = "Call/v1")]
+    Call(Call),
+    #[serde(rename = "Get/v1")]
+    Get(Get),
+    #[serde(rename = "Grouping/v1")]
     Gropuping(Grouping),
+    #[serde(rename = "Literal/v1")]
     Literal(Literal),
-    // Logical(Logical),
-    // Set(Set),
-    // Super(Super),
-    This,
+    #[serde(rename = "Logical/v1")]
+    Logical(Logical),
+    #[serde(rename = "Set/v1")]
+    Set(Set),
+    #[serde(rename = "Super/v1")]
+    Super(Super),
+    #[serde(rename = "This/v1")]
+    This(This),
+    #[serde(rename = "Unary/v1")]
     Unary(Unary),
+    #[serde(rename = "Variable/v1")]
     Variable(Variable),
 }
 
 /// Assignment expression.
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Assign {
-    name: String,
-    value: Box<Expr>,
+    pub(crate) name: Symbol,
+    pub(crate) value: Box<Expr>,
+    // Number of scopes between the assignment and the variable, `None` for globals
+    #[serde(skip)]
+    pub(crate) depth: Cell<Option<usize>>,
+    #[serde(default)]
+    pub(crate) span: Span,
 }
 
 /// Binary expression.
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Binary {
-    left: Box<Expr>,
-    right: Box<Expr>,
-    operator: BinaryOp,
+    pub(crate) left: Box<Expr>,
+    pub(crate) right: Box<Expr>,
+    pub(crate) operator: BinaryOp,
+    #[serde(default)]
+    pub(crate) span: Span,
+    #[serde(default)]
+    pub(crate) operator_span: Span,
 }
 
 /// Binary expression operators.
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub enum BinaryOp {
     Less,
     LessEqual,
@@ -56,350 +86,500 @@ pub enum BinaryOp {
     Sub,
 }
 
-// /// Function call expression.
-// #[derive(Serialize)]
-// pub struct Call {
-//     calle: Box<Expr>,
-//     paren: Token,
-//     arguments: Vec<Expr>,
-// }
+/// Function call expression.
+#[derive(Deserialize, Serialize)]
+pub struct Call {
+    pub(crate) callee: Box<Expr>,
+    pub(crate) arguments: Vec<Expr>,
+    #[serde(default)]
+    pub(crate) span: Span,
+}
 
-// #[derive(Serialize)]
-// pub struct Get {
-//     object: Box<Expr>,
-//     name: Token,
-// }
+/// Property access expression.
+#[derive(Deserialize, Serialize)]
+pub struct Get {
+    pub(crate) object: Box<Expr>,
+    pub(crate) name: Symbol,
+    #[serde(default)]
+    pub(crate) span: Span,
+}
 
 /// Grouping expression.
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Grouping {
-    expression: Box<Expr>,
+    pub(crate) expression: Box<Expr>,
+    #[serde(default)]
+    pub(crate) span: Span,
 }
 
 /// Literal expression.
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Literal {
-    value: LiteralValue,
+    pub(crate) value: LiteralValue,
+    #[serde(default)]
+    pub(crate) span: Span,
 }
 
-// #[derive(Serialize)]
-// pub struct Logical {
-//     left: Box<Expr>,
-//     right: Box<Expr>,
-//     operator: Token,
-// }
+/// Logical expression.
+#[derive(Deserialize, Serialize)]
+pub struct Logical {
+    pub(crate) left: Box<Expr>,
+    pub(crate) right: Box<Expr>,
+    pub(crate) operator: LogicalOp,
+    #[serde(default)]
+    pub(crate) span: Span,
+}
 
-// #[derive(Serialize)]
-// pub struct Set {
-//     object: Box<Expr>,
-//     name: Token,
-//     value: Box<Expr>,
-// }
+/// Logical expression operators.
+#[derive(Deserialize, Serialize)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
 
-// #[derive(Serialize)]
-// pub struct Super {
-//     keyword: Token,
-//     method: Token,
-// }
+/// Field assignment expression.
+#[derive(Deserialize, Serialize)]
+pub struct Set {
+    pub(crate) object: Box<Expr>,
+    pub(crate) name: Symbol,
+    pub(crate) value: Box<Expr>,
+    #[serde(default)]
+    pub(crate) span: Span,
+}
+
+/// Superclass method access expression.
+#[derive(Deserialize, Serialize)]
+pub struct Super {
+    pub(crate) method: Symbol,
+    // Number of scopes between the expression and the scope binding `super`
+    #[serde(skip)]
+    pub(crate) depth: Cell<Option<usize>>,
+    #[serde(default)]
+    pub(crate) span: Span,
+}
+
+/// This expression.
+#[derive(Deserialize, Serialize)]
+pub struct This {
+    // Number of scopes between the expression and the scope binding `this`
+    #[serde(skip)]
+    pub(crate) depth: Cell<Option<usize>>,
+    #[serde(default)]
+    pub(crate) span: Span,
+}
 
 /// Unary expression.
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Unary {
-    operand: Box<Expr>,
-    operator: UnaryOp,
+    pub(crate) operand: Box<Expr>,
+    pub(crate) operator: UnaryOp,
+    #[serde(default)]
+    pub(crate) span: Span,
+    #[serde(default)]
+    pub(crate) operator_span: Span,
 }
 
 /// Unary expression operators.
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub enum UnaryOp {
     Not,
     Neg,
 }
 
 /// Variable expression.
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Variable {
-    name: String,
+    pub(crate) name: Symbol,
+    // Number of scopes between the expression and the variable, `None` for globals
+    #[serde(skip)]
+    pub(crate) depth: Cell<Option<usize>>,
+    #[serde(default)]
+    pub(crate) span: Span,
 }
 
 /// Literal type.
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 pub enum LiteralValue {
     Bool(bool),
     Nil,
     Number(f64),
-    String(String),
+    String(Symbol),
 }
 
 impl Expr {
-    /// Prints the expression tree to the standard output in JSON format using [`serde_json`].
-    ///
-    /// # Panics
-    /// Meant to be used for debugging purposes only.
-    /// Unintended side-effects may arise.
-    /// Refer to [`serde_json`] documentation for safe usage.
-    pub fn print(&self) {
-        let expr_json = serde_json::to_string_pretty(self).unwrap();
-        println!("{}", expr_json);
-    }
-
-    /// Evaluates an expression and returns its value, [`Diagnostic`] is returned if there is an
-    /// error.
-    pub fn eval(&self) -> Result<LoxValue, Diagnostic> {
+    /// Returns the span of source code the expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Assign(expr) => expr.span,
+            Expr::Binary(expr) => expr.span,
+            Expr::Call(expr) => expr.span,
+            Expr::Get(expr) => expr.span,
+            Expr::Gropuping(expr) => expr.span,
+            Expr::Literal(expr) => expr.span,
+            Expr::Logical(expr) => expr.span,
+            Expr::Set(expr) => expr.span,
+            Expr::Super(expr) => expr.span,
+            Expr::This(expr) => expr.span,
+            Expr::Unary(expr) => expr.span,
+            Expr::Variable(expr) => expr.span,
+        }
+    }
+
+    /// Evaluates an expression in an [`Environment`] and returns its value, [`Diagnostic`] is
+    /// returned if there is an error.
+    pub fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
         match self {
             Expr::Literal(expr) => expr.eval(),
-            Expr::Unary(expr) => expr.eval(),
-            Expr::Binary(expr) => expr.eval(),
-            _ => todo!(),
+            Expr::Unary(expr) => expr.eval(env),
+            Expr::Binary(expr) => expr.eval(env),
+            Expr::Variable(expr) => expr.eval(env),
+            Expr::Assign(expr) => expr.eval(env),
+            Expr::Logical(expr) => expr.eval(env),
+            Expr::Call(expr) => expr.eval(env),
+            Expr::Get(expr) => expr.eval(env),
+            Expr::Set(expr) => expr.eval(env),
+            Expr::Super(expr) => expr.eval(env),
+            Expr::This(expr) => expr.eval(env),
+            Expr::Gropuping(expr) => expr.expression.eval(env),
+        }
+    }
+}
+
+impl Assign {
+    pub fn new(name: Symbol, value: Expr, span: Span) -> Self {
+        Assign {
+            name,
+            value: Box::new(value),
+            depth: Cell::new(None),
+            span,
+        }
+    }
+
+    fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
+        let value = self.value.eval(env)?;
+        let assigned = match self.depth.get() {
+            Some(depth) => env.assign_at(depth, &self.name, value.clone()),
+            None => env.assign_global(&self.name, value.clone()),
+        };
+        if !assigned {
+            return Err(Assign::undefined(&self.name, self.span));
+        }
+        Ok(value)
+    }
+
+    /// Returns the error for an assignment to a variable which is not defined.
+    pub(crate) fn undefined(name: &str, span: Span) -> Diagnostic {
+        Diagnostic::LoxError {
+            kind: ErrorKind::UndefinedVariable,
+            span,
+            message: format!("cannot assign to undefined variable [{name}]"),
+            notes: Vec::new(),
         }
     }
 }
 
 impl Binary {
-    pub fn new(left: Expr, right: Expr, operator: BinaryOp) -> Self {
+    pub fn new(left: Expr, right: Expr, operator: BinaryOp, operator_span: Span) -> Self {
         // Add error checking code to panic if the operator is not a binary operator
+        let span = left.span().to(right.span());
         Binary {
             left: Box::new(left),
             right: Box::new(right),
             operator,
+            span,
+            operator_span,
         }
     }
 
-    fn eval(&self) -> Result<LoxValue, Diagnostic> {
-        let left = self.left.eval()?;
-        let right = self.right.eval()?;
+    // Errors are reported at the operator
+    fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
+        let left = self.left.eval(env)?;
+        let mark = env.hold(&left);
+        let right = self.right.eval(env);
+        env.release(mark);
+        self.operate(left, right?)
+    }
+
+    /// Applies the operator to the values of the operands, with the same errors as evaluating the
+    /// expression.
+    pub(crate) fn operate(&self, left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+        let span = self.operator_span;
         match self.operator {
-            BinaryOp::Add => Binary::add(left, right),
-            BinaryOp::Sub => Binary::sub(left, right),
-            BinaryOp::Mul => Binary::mul(left, right),
-            BinaryOp::Div => Binary::div(left, right),
-            BinaryOp::Less => Binary::lt(left, right),
-            BinaryOp::LessEqual => Binary::le(left, right),
-            BinaryOp::Greater => Binary::gt(left, right),
-            BinaryOp::GreaterEqual => Binary::ge(left, right),
-            BinaryOp::Equal => Binary::eq(left, right),
-            BinaryOp::NotEqual => Binary::ne(left, right),
+            BinaryOp::Add => Binary::add(left, right, span),
+            BinaryOp::Sub => Binary::sub(left, right, span),
+            BinaryOp::Mul => Binary::mul(left, right, span),
+            BinaryOp::Div => Binary::div(left, right, span),
+            BinaryOp::Less => Binary::lt(left, right, span),
+            BinaryOp::LessEqual => Binary::le(left, right, span),
+            BinaryOp::Greater => Binary::gt(left, right, span),
+            BinaryOp::GreaterEqual => Binary::ge(left, right, span),
+            BinaryOp::Equal => Binary::eq(left, right, span),
+            BinaryOp::NotEqual => Binary::ne(left, right, span),
         }
     }
 
-    fn add(left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn add(left: LoxValue, right: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
         match left {
             LoxValue::Number(left) => {
-                // TODO: Add line information
                 match right {
                     LoxValue::Number(right) => Ok(LoxValue::Number(left + right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be added to value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be added to value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be added to value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be added to value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             }
             LoxValue::String(left) => {
                 match right {
-                    LoxValue::String(right) => Ok(LoxValue::String(left + &right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type String cannot be added to value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type String cannot be added to value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::String(right) => Ok(LoxValue::String([&*left, &*right].concat().into())),
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type String cannot be added to value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type String cannot be added to value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             }
             LoxValue::Nil => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: "value [Nil] cannot be added".to_string(),
+                notes: Vec::new(),
             }),
             _ => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: format!("value [{left}] of type {} cannot be added", left.type_str()),
+                notes: Vec::new(),
             }),
         }
     }
 
-    fn sub(left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn sub(left: LoxValue, right: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
         match left {
             LoxValue::Number(left) => {
-                // TODO: Add line information
                 match right {
                     LoxValue::Number(right) => Ok(LoxValue::Number(left - right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be subtracted by value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be subtraced by value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be subtracted by value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be subtraced by value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             }
             LoxValue::Nil => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: "value [Nil] cannot be subtracted from".to_string(),
+                notes: Vec::new(),
             }),
             _ => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: format!(
                     "value [{left}] of type {} cannot be subtracted from",
                     left.type_str()
                 ),
+                notes: Vec::new(),
             }),
         }
     }
 
-    fn mul(left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn mul(left: LoxValue, right: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
         match left {
             LoxValue::Number(left) => {
-                // TODO: Add line information
                 match right {
                     LoxValue::Number(right) => Ok(LoxValue::Number(left * right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be multiplied by value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be multiplied by value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be multiplied by value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be multiplied by value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             }
             LoxValue::Nil => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: "value [Nil] cannot be multiplied".to_string(),
+                notes: Vec::new(),
             }),
             _ => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: format!(
                     "value [{left}] of type {} cannot be multiplied",
                     left.type_str()
                 ),
+                notes: Vec::new(),
             }),
         }
     }
 
-    fn div(left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn div(left: LoxValue, right: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
         match left {
             LoxValue::Number(left) => {
-                // TODO: Add line information
                 match right {
                     LoxValue::Number(right) => Ok(LoxValue::Number(left / right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be divided by value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be divided by value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be divided by value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be divided by value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             }
             LoxValue::Nil => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: "value [Nil] cannot be divided".to_string(),
+                notes: Vec::new(),
             }),
             _ => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: format!(
                     "value [{left}] of type {} cannot be divided",
                     left.type_str()
                 ),
+                notes: Vec::new(),
             }),
         }
     }
 
-    fn lt(left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn lt(left: LoxValue, right: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
         match left {
             LoxValue::Number(left) => {
                 match right {
-                // TODO: Add line information
                     LoxValue::Number(right) => Ok(LoxValue::Bool(left < right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             }
             LoxValue::Nil => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: "value [Nil] cannot be compared".to_string(),
+                notes: Vec::new(),
             }),
             _ => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: format!(
                     "value [{left}] of type {} cannot be compared",
                     left.type_str()
                 ),
+                notes: Vec::new(),
             }),
         }
     }
 
-    fn le(left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn le(left: LoxValue, right: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
         match left {
             LoxValue::Number(left) => {
                 match right {
-                    // TODO: Add line information
                     LoxValue::Number(right) => Ok(LoxValue::Bool(left <= right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             }
             LoxValue::Nil => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: "value [Nil] cannot be compared".to_string(),
+                notes: Vec::new(),
             }),
             _ => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: format!(
                     "value [{left}] of type {} cannot be compared",
                     left.type_str()
                 ),
+                notes: Vec::new(),
             }),
         }
     }
 
-    fn gt(left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn gt(left: LoxValue, right: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
         match left {
             LoxValue::Number(left) => {
                 match right {
-                    // TODO: Add line information
                     LoxValue::Number(right) => Ok(LoxValue::Bool(left > right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             }
             LoxValue::Nil => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: "value [Nil] cannot be compared".to_string(),
+                notes: Vec::new(),
             }),
             _ => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: format!(
                     "value [{left}] of type {} cannot be compared",
                     left.type_str()
                 ),
+                notes: Vec::new(),
             }),
         }
     }
 
-    fn ge(left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn ge(left: LoxValue, right: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
         match left {
             LoxValue::Number(left) => {
                 match right {
-                    // TODO: Add line information
                     LoxValue::Number(right) => Ok(LoxValue::Bool(left >= right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             }
             LoxValue::Nil => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: "value [Nil] cannot be compared".to_string(),
+                notes: Vec::new(),
             }),
             _ => Err(Diagnostic::LoxError {
-                line: 69,
+                kind: ErrorKind::InvalidOperand,
+                span,
                 message: format!(
                     "value [{left}] of type {} cannot be compared",
                     left.type_str()
                 ),
+                notes: Vec::new(),
             }),
         }
     }
 
-    fn eq(left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn eq(left: LoxValue, right: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
         match left {
             LoxValue::Number(left) => {
                 match right {
-                // TODO: Add line information
                     LoxValue::Number(right) => Ok(LoxValue::Bool(left == right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             },
             LoxValue::String(left) => {
                 match right {
                     LoxValue::String(right) => Ok(LoxValue::Bool(left == right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type String cannot be compared with value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type String cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             },
             LoxValue::Bool(left) => {
                 match right {
                     LoxValue::Bool(right) => Ok(LoxValue::Bool(left == right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Bool cannot be compared with value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Bool cannot be compared with value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Bool cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Bool cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
+                }
+            },
+            LoxValue::Function(left) => {
+                match right {
+                    LoxValue::Function(right) => Ok(LoxValue::Bool(Rc::ptr_eq(&left, &right))),
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Function cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Function cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
+                }
+            },
+            LoxValue::Class(left) => {
+                match right {
+                    LoxValue::Class(right) => Ok(LoxValue::Bool(Rc::ptr_eq(&left, &right))),
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Class cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Class cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
+                }
+            },
+            LoxValue::Instance(left) => {
+                match right {
+                    LoxValue::Instance(right) => Ok(LoxValue::Bool(Rc::ptr_eq(&left, &right))),
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Instance cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Instance cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             },
             LoxValue::Nil => {
@@ -411,28 +591,48 @@ impl Binary {
         }
     }
 
-    fn ne(left: LoxValue, right: LoxValue) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn ne(left: LoxValue, right: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
         match left {
             LoxValue::Number(left) => {
                 match right {
-                // TODO: Add line information
                     LoxValue::Number(right) => Ok(LoxValue::Bool(left != right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             },
             LoxValue::String(left) => {
                 match right {
                     LoxValue::String(right) => Ok(LoxValue::Bool(left != right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type String cannot be compared with value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type String cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Number cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             },
             LoxValue::Bool(left) => {
                 match right {
                     LoxValue::Bool(right) => Ok(LoxValue::Bool(left != right)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Bool cannot be compared with value [Nil]") }),
-                    _ => Err(Diagnostic::LoxError { line: 69, message: format!("value [{left}] of type Bool cannot be compared with value [{right}] of type {}", right.type_str() ) })
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Bool cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Bool cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
+                }
+            },
+            LoxValue::Function(left) => {
+                match right {
+                    LoxValue::Function(right) => Ok(LoxValue::Bool(!Rc::ptr_eq(&left, &right))),
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Function cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Function cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
+                }
+            },
+            LoxValue::Class(left) => {
+                match right {
+                    LoxValue::Class(right) => Ok(LoxValue::Bool(!Rc::ptr_eq(&left, &right))),
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Class cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Class cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
+                }
+            },
+            LoxValue::Instance(left) => {
+                match right {
+                    LoxValue::Instance(right) => Ok(LoxValue::Bool(!Rc::ptr_eq(&left, &right))),
+                    LoxValue::Nil => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Instance cannot be compared with value [Nil]"), notes: Vec::new() }),
+                    _ => Err(Diagnostic::LoxError { kind: ErrorKind::InvalidOperand, span, message: format!("value [{left}] of type Instance cannot be compared with value [{right}] of type {}", right.type_str() ), notes: Vec::new() })
                 }
             },
             LoxValue::Nil => {
@@ -445,54 +645,287 @@ impl Binary {
     }
 }
 
+impl Call {
+    pub fn new(callee: Expr, arguments: Vec<Expr>, span: Span) -> Self {
+        Call {
+            callee: Box::new(callee),
+            arguments,
+            span,
+        }
+    }
+
+    fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
+        let callee = self.callee.eval(env)?;
+        let mark = env.hold(&callee);
+        let arguments = self
+            .arguments
+            .iter()
+            .map(|argument| {
+                let value = argument.eval(env)?;
+                env.hold(&value);
+                Ok(value)
+            })
+            .collect::<Result<Vec<_>, Diagnostic>>();
+        env.release(mark);
+        let arguments = arguments?;
+        match callee {
+            LoxValue::Function(function) => function.call(arguments, env, self.span),
+            LoxValue::Class(class) => LoxClass::call(&class, arguments, env, self.span),
+            _ => Err(Call::not_callable(&callee, self.callee.span())),
+        }
+    }
+
+    /// Returns the error for a call to a value which is not a function or class, reported at the
+    /// span of the callee.
+    pub(crate) fn not_callable(callee: &LoxValue, span: Span) -> Diagnostic {
+        let message = match callee {
+            LoxValue::Nil => "value [Nil] cannot be called".to_string(),
+            _ => format!(
+                "value [{callee}] of type {} cannot be called",
+                callee.type_str()
+            ),
+        };
+        Diagnostic::LoxError {
+            kind: ErrorKind::NotCallable,
+            span,
+            message,
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl Get {
+    pub fn new(object: Expr, name: Symbol, span: Span) -> Self {
+        Get {
+            object: Box::new(object),
+            name,
+            span,
+        }
+    }
+
+    /// Splits the expression into the object and the name of the property.
+    pub fn into_parts(self) -> (Expr, Symbol) {
+        (*self.object, self.name)
+    }
+
+    fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
+        match self.object.eval(env)? {
+            LoxValue::Instance(instance) => LoxInstance::get(&instance, &self.name, self.span),
+            object => Err(Get::no_properties(&object, self.span)),
+        }
+    }
+
+    /// Returns the error for a property read from a value which is not an instance.
+    pub(crate) fn no_properties(object: &LoxValue, span: Span) -> Diagnostic {
+        Diagnostic::LoxError {
+            kind: ErrorKind::NotAnInstance,
+            span,
+            message: format!(
+                "value [{object}] of type {} has no properties",
+                object.type_str()
+            ),
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl Grouping {
+    pub fn new(expression: Expr, span: Span) -> Self {
+        Grouping {
+            expression: Box::new(expression),
+            span,
+        }
+    }
+}
+
+impl Logical {
+    pub fn new(left: Expr, right: Expr, operator: LogicalOp) -> Self {
+        let span = left.span().to(right.span());
+        Logical {
+            left: Box::new(left),
+            right: Box::new(right),
+            operator,
+            span,
+        }
+    }
+
+    // Short-circuits, returning the operand that decides the result without coercing it
+    fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
+        let left = self.left.eval(env)?;
+        match self.operator {
+            LogicalOp::And if !left.is_truthy() => Ok(left),
+            LogicalOp::Or if left.is_truthy() => Ok(left),
+            _ => self.right.eval(env),
+        }
+    }
+}
+
+impl Set {
+    pub fn new(object: Expr, name: Symbol, value: Expr, span: Span) -> Self {
+        Set {
+            object: Box::new(object),
+            name,
+            value: Box::new(value),
+            span,
+        }
+    }
+
+    fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
+        match self.object.eval(env)? {
+            LoxValue::Instance(instance) => {
+                let mark = env.hold(&LoxValue::Instance(Rc::clone(&instance)));
+                let value = self.value.eval(env);
+                env.release(mark);
+                let value = value?;
+                instance.set(self.name.clone(), value.clone());
+                Ok(value)
+            }
+            object => Err(Set::no_fields(&object, self.span)),
+        }
+    }
+
+    /// Returns the error for a field set on a value which is not an instance.
+    pub(crate) fn no_fields(object: &LoxValue, span: Span) -> Diagnostic {
+        Diagnostic::LoxError {
+            kind: ErrorKind::NotAnInstance,
+            span,
+            message: format!(
+                "value [{object}] of type {} has no fields",
+                object.type_str()
+            ),
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl Super {
+    pub fn new(method: Symbol, span: Span) -> Self {
+        Super {
+            method,
+            depth: Cell::new(None),
+            span,
+        }
+    }
+
+    // Looks up the method starting from the superclass of the class the method was defined in,
+    // binding it to the current instance, which is bound in the scope enclosed by the superclass
+    fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
+        let depth = self.depth.get();
+        let (Some(LoxValue::Class(superclass)), Some(instance)) = (
+            depth.and_then(|depth| env.get_at(depth, &Symbol::super_())),
+            depth
+                .and_then(|depth| depth.checked_sub(1))
+                .and_then(|depth| env.get_at(depth, &Symbol::this())),
+        ) else {
+            return Err(Diagnostic::LoxError {
+                kind: ErrorKind::InvalidSuper,
+                span: self.span,
+                message: "cannot use [super] outside of a subclass".to_string(),
+                notes: Vec::new(),
+            });
+        };
+        match superclass.find_method(&self.method) {
+            Some(method) => Ok(LoxValue::Function(Rc::new(method.bind(instance)))),
+            None => Err(Super::undefined(&self.method, &superclass, self.span)),
+        }
+    }
+
+    /// Returns the error for a method which is not defined by a superclass.
+    pub(crate) fn undefined(method: &str, superclass: &LoxClass, span: Span) -> Diagnostic {
+        Diagnostic::LoxError {
+            kind: ErrorKind::UndefinedProperty,
+            span,
+            message: format!("undefined property [{method}] of superclass [{superclass}]"),
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl This {
+    pub fn new(span: Span) -> Self {
+        This {
+            depth: Cell::new(None),
+            span,
+        }
+    }
+
+    fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
+        self.depth
+            .get()
+            .and_then(|depth| env.get_at(depth, &Symbol::this()))
+            .ok_or_else(|| Diagnostic::LoxError {
+                kind: ErrorKind::InvalidThis,
+                span: self.span,
+                message: "cannot use [this] outside of a class".to_string(),
+                notes: Vec::new(),
+            })
+    }
+}
+
 impl Unary {
-    pub fn new(operand: Expr, operator: UnaryOp) -> Self {
+    pub fn new(operand: Expr, operator: UnaryOp, operator_span: Span) -> Self {
         // Add error checking code to panic if the operator is not a binary operator
+        let span = operator_span.to(operand.span());
         Unary {
             operand: Box::new(operand),
             operator,
+            span,
+            operator_span,
         }
     }
 
-    fn eval(&self) -> Result<LoxValue, Diagnostic> {
-        let operand = self.operand.eval()?;
+    // Errors are reported at the operator
+    fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
+        let operand = self.operand.eval(env)?;
+        self.operate(operand)
+    }
+
+    /// Applies the operator to the value of the operand, with the same errors as evaluating the
+    /// expression.
+    pub(crate) fn operate(&self, operand: LoxValue) -> Result<LoxValue, Diagnostic> {
         match self.operator {
-            UnaryOp::Not => Ok(LoxValue::Bool(!operand.is_truthy())),
-            UnaryOp::Neg => {
-                // TODO: Add line/col information
-                // Wrap in function/implement traits
-                // TODO: Check if bools can be negated
-                match operand {
-                    LoxValue::Number(num) => Ok(LoxValue::Number(-num)),
-                    LoxValue::Nil => Err(Diagnostic::LoxError {
-                        line: 69,
-                        message: "value [Nil] cannot be negated".to_string(),
-                    }),
-                    _ => Err(Diagnostic::LoxError {
-                        line: 69,
-                        message: format!(
-                            "value [{operand}] of type {} cannot be negated",
-                            operand.type_str()
-                        )
-                        .to_string(),
-                    }),
-                }
-            }
+            UnaryOp::Not => Ok(Unary::not(operand)),
+            UnaryOp::Neg => Unary::neg(operand, self.operator_span),
+        }
+    }
+
+    pub(crate) fn not(operand: LoxValue) -> LoxValue {
+        LoxValue::Bool(!operand.is_truthy())
+    }
+
+    // TODO: Check if bools can be negated
+    pub(crate) fn neg(operand: LoxValue, span: Span) -> Result<LoxValue, Diagnostic> {
+        match operand {
+            LoxValue::Number(num) => Ok(LoxValue::Number(-num)),
+            LoxValue::Nil => Err(Diagnostic::LoxError {
+                kind: ErrorKind::InvalidOperand,
+                span,
+                message: "value [Nil] cannot be negated".to_string(),
+                notes: Vec::new(),
+            }),
+            _ => Err(Diagnostic::LoxError {
+                kind: ErrorKind::InvalidOperand,
+                span,
+                message: format!(
+                    "value [{operand}] of type {} cannot be negated",
+                    operand.type_str()
+                ),
+                notes: Vec::new(),
+            }),
         }
     }
 }
 
 impl Literal {
-    pub fn new(value: LiteralValue) -> Self {
-        Literal { value }
+    pub fn new(value: LiteralValue, span: Span) -> Self {
+        Literal { value, span }
     }
 
     // TODO: Consider making `LiteralValue` Lox value
-    // TODO: Remove clone and make LoxValue hold string references
-    fn eval(&self) -> Result<LoxValue, Diagnostic> {
+    pub(crate) fn eval(&self) -> Result<LoxValue, Diagnostic> {
         match &self.value {
             LiteralValue::Bool(val) => Ok(LoxValue::Bool(*val)),
-            LiteralValue::String(val) => Ok(LoxValue::String(val.clone())),
+            LiteralValue::String(val) => Ok(LoxValue::String(val.to_lox_string())),
             LiteralValue::Nil => Ok(LoxValue::Nil),
             LiteralValue::Number(val) => Ok(LoxValue::Number(*val)),
         }
@@ -500,23 +933,56 @@ impl Literal {
 }
 
 impl Variable {
-    pub fn new(name: String) -> Self {
-        Variable { name }
+    pub fn new(name: Symbol, span: Span) -> Self {
+        Variable {
+            name,
+            depth: Cell::new(None),
+            span,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn eval(&self, env: &mut Environment) -> Result<LoxValue, Diagnostic> {
+        let value = match self.depth.get() {
+            Some(depth) => env.get_at(depth, &self.name),
+            None => env.get_global(&self.name),
+        };
+        value.ok_or_else(|| Variable::undefined(&self.name, self.span))
+    }
+
+    /// Returns the error for a read of a variable which is not defined.
+    pub(crate) fn undefined(name: &str, span: Span) -> Diagnostic {
+        Diagnostic::LoxError {
+            kind: ErrorKind::UndefinedVariable,
+            span,
+            message: format!("undefined variable [{name}]"),
+            notes: Vec::new(),
+        }
     }
 }
 
 /// Lox value.
-#[derive(Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum LoxValue {
-    // TODO: Add object type
     Nil,
     Bool(bool),
     Number(f64),
-    String(String),
+    String(LoxString),
+    #[serde(skip)]
+    Function(Rc<LoxFunction>),
+    #[serde(skip)]
+    Class(Rc<LoxClass>),
+    #[serde(skip)]
+    Instance(Rc<LoxInstance>),
 }
 
 impl LoxValue {
-    fn is_truthy(&self) -> bool {
+    /// Returns the truthiness of a value, `nil` and `false` are falsey and everything else is
+    /// truthy.
+    pub fn is_truthy(&self) -> bool {
         match self {
             LoxValue::Nil => false,
             LoxValue::Bool(val) => *val,
@@ -524,13 +990,16 @@ impl LoxValue {
         }
     }
 
-    // Returns the type of the Lox value as a string litral
-    fn type_str(&self) -> &str {
+    /// Returns the type of the Lox value as a string literal.
+    pub fn type_str(&self) -> &str {
         match self {
             LoxValue::Number(_) => "Number",
             LoxValue::String(_) => "String",
             LoxValue::Bool(_) => "Bool",
             LoxValue::Nil => "Nil",
+            LoxValue::Function(_) => "Function",
+            LoxValue::Class(_) => "Class",
+            LoxValue::Instance(_) => "Instance",
         }
     }
 }
@@ -542,6 +1011,9 @@ impl fmt::Display for LoxValue {
             LoxValue::Bool(val) => write!(f, "{val}"),
             LoxValue::String(string) => write!(f, "{string}"), // TODO: Maybe wrap in quotes?
             LoxValue::Nil => write!(f, "nil"),
+            LoxValue::Function(function) => write!(f, "{function}"),
+            LoxValue::Class(class) => write!(f, "{class}"),
+            LoxValue::Instance(instance) => write!(f, "{instance}"),
         }
     }
 }
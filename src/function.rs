@@ -0,0 +1,210 @@
+//! Lox functions.
+//!
+//! Functions are first-class Lox values created when a function declaration is executed.
+//! Each function is a closure, capturing the scope it was declared in.
+//! Methods are functions bound to an instance, which is available to the method as `this`.
+//!
+//! Functions created by the [virtual machine](crate::vm) are compiled closures instead, which
+//! capture individual variables. Functions can be called by either backend, so a function created
+//! by one backend keeps working when the environment it is stored in is used by the other.
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::environment::{self, Environment, Scope, ScopeRef};
+use crate::expression::LoxValue;
+use crate::gc::Tracer;
+use crate::span::Span;
+use crate::statement::{self, Flow, Function};
+use crate::symbol::Symbol;
+use crate::vm::{Closure, Vm};
+use crate::{Diagnostic, ErrorKind};
+
+/// Callable function value.
+pub struct LoxFunction {
+    body: Body,
+}
+
+// Code run when the function is called, depending on the backend which created the function
+enum Body {
+    Tree {
+        declaration: Rc<Function>,
+        closure: ScopeRef,
+        is_initializer: bool,
+    },
+    Bytecode {
+        closure: Rc<Closure>,
+        // Instance the method is bound to, passed to the closure as `this`
+        receiver: Option<LoxValue>,
+    },
+}
+
+impl LoxFunction {
+    pub fn new(declaration: Rc<Function>, closure: ScopeRef, is_initializer: bool) -> Self {
+        LoxFunction {
+            body: Body::Tree {
+                declaration,
+                closure,
+                is_initializer,
+            },
+        }
+    }
+
+    /// Creates a function from a closure compiled to bytecode.
+    pub fn compiled(closure: Rc<Closure>) -> Self {
+        LoxFunction {
+            body: Body::Bytecode {
+                closure,
+                receiver: None,
+            },
+        }
+    }
+
+    /// Binds the function to an instance, returning a method where `this` refers to the instance.
+    pub fn bind(&self, instance: LoxValue) -> LoxFunction {
+        match &self.body {
+            Body::Tree {
+                declaration,
+                closure,
+                is_initializer,
+            } => {
+                let scope = Scope::enclosed_by(closure);
+                scope.borrow_mut().define(Symbol::this(), instance);
+                LoxFunction::new(Rc::clone(declaration), scope, *is_initializer)
+            }
+            Body::Bytecode { closure, .. } => LoxFunction {
+                body: Body::Bytecode {
+                    closure: Rc::clone(closure),
+                    receiver: Some(instance),
+                },
+            },
+        }
+    }
+
+    /// Returns the compiled closure of the function and the instance it is bound to, `None` if the
+    /// function is executed by the tree-walking interpreter.
+    pub(crate) fn compiled_closure(&self) -> Option<(&Rc<Closure>, Option<&LoxValue>)> {
+        match &self.body {
+            Body::Tree { .. } => None,
+            Body::Bytecode { closure, receiver } => Some((closure, receiver.as_ref())),
+        }
+    }
+
+    /// Returns the name the function was declared with.
+    pub fn name(&self) -> &str {
+        match &self.body {
+            Body::Tree { declaration, .. } => declaration.name(),
+            Body::Bytecode { closure, .. } => &closure.function.name,
+        }
+    }
+
+    /// Returns the number of parameters the function takes.
+    pub fn arity(&self) -> usize {
+        match &self.body {
+            Body::Tree { declaration, .. } => declaration.params().len(),
+            Body::Bytecode { closure, .. } => closure.function.arity,
+        }
+    }
+
+    pub(crate) fn trace(&self, tracer: &mut Tracer) {
+        match &self.body {
+            Body::Tree { closure, .. } => tracer.scope(closure),
+            Body::Bytecode { closure, receiver } => {
+                tracer.closure(closure);
+                if let Some(receiver) = receiver {
+                    tracer.value(receiver);
+                }
+            }
+        }
+    }
+
+    /// Checks that the function is called with as many arguments as it has parameters,
+    /// [`Diagnostic`] is returned at the span of the call otherwise.
+    pub(crate) fn check_arity(&self, arguments: usize, span: Span) -> Result<(), Diagnostic> {
+        if arguments == self.arity() {
+            return Ok(());
+        }
+        Err(Diagnostic::LoxError {
+            kind: ErrorKind::ArityMismatch,
+            span,
+            message: format!(
+                "function [{self}] expected {} arguments but got {arguments}",
+                self.arity()
+            ),
+            notes: vec![
+                "every parameter takes exactly one argument, there are no optional parameters"
+                    .to_string(),
+            ],
+        })
+    }
+
+    /// Calls the function with a list of arguments, executing its body in a fresh scope enclosed by
+    /// the captured scope, where the parameters are bound to the arguments. Returns the value of
+    /// the function, [`Diagnostic`] is returned if there is an error.
+    ///
+    /// Errors are reported at the span of the call. Initializers always return the instance they
+    /// were bound to. Functions compiled to bytecode are executed by a new virtual machine.
+    ///
+    /// The call continues on a new segment of native stack if the current one is running out, so
+    /// deep recursion is reported as [`ErrorKind::StackOverflow`] instead of overflowing the stack.
+    pub fn call(
+        &self,
+        arguments: Vec<LoxValue>,
+        env: &mut Environment,
+        span: Span,
+    ) -> Result<LoxValue, Diagnostic> {
+        stacker::maybe_grow(environment::RED_ZONE, environment::STACK_SEGMENT, || {
+            self.call_body(arguments, env, span)
+        })
+    }
+
+    fn call_body(
+        &self,
+        arguments: Vec<LoxValue>,
+        env: &mut Environment,
+        span: Span,
+    ) -> Result<LoxValue, Diagnostic> {
+        let Body::Tree {
+            declaration,
+            closure,
+            is_initializer,
+        } = &self.body
+        else {
+            return Vm::new(env).call_compiled(self, arguments, span);
+        };
+        self.check_arity(arguments.len(), span)?;
+        env.enter_call(closure, span)?;
+        for (param, argument) in declaration.params().iter().zip(arguments) {
+            env.define(param.clone(), argument);
+        }
+        let result = statement::execute_block(declaration.body(), env);
+        env.exit_call();
+        match result? {
+            _ if *is_initializer => Ok(closure
+                .borrow()
+                .get(&Symbol::this())
+                .unwrap_or(LoxValue::Nil)),
+            Flow::Return(value) => Ok(value),
+            Flow::Normal => Ok(LoxValue::Nil),
+        }
+    }
+}
+
+// Functions are only equal to themselves
+impl PartialEq for LoxFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
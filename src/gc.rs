@@ -0,0 +1,385 @@
+//! Lox garbage collector.
+//!
+//! Heap objects are reference-counted, so most of them are freed as soon as they become
+//! unreachable. Reference counting leaks cycles though, such as an instance holding a closure that
+//! captures the instance, which the collector reclaims with a mark-and-sweep pass.
+//!
+//! A cycle can only be formed through an object whose contents change after it is created: a
+//! scope, an instance or a variable captured by a closure. The [`Heap`] keeps weak references to
+//! these objects. A collection marks every object reachable from the roots, which are the globals,
+//! the open environments of the tree-walking interpreter, the values it is holding while
+//! evaluating an expression and the value stack of the virtual machine. Registered objects which
+//! were not marked are swept by clearing their contents, which drops the references forming their
+//! cycles.
+//!
+//! Collections also remove the strings no longer used from the [interner](crate::symbol).
+//!
+//! Collections only happen before a statement or an instruction is executed, where every live
+//! value is reachable from the roots. They are triggered once the bytes allocated for registered
+//! objects exceed a threshold, which grows with the bytes still live after each collection.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::class::LoxInstance;
+use crate::environment::{Scope, ScopeRef};
+use crate::expression::LoxValue;
+use crate::symbol;
+use crate::vm::{Closure, Upvalue, UpvalueRef};
+
+/// Minimum number of bytes allocated before a collection is triggered.
+pub const MIN_THRESHOLD: usize = 1024 * 1024;
+
+/// Factor the bytes live after a collection are multiplied by to get the next threshold.
+pub const GROWTH_FACTOR: usize = 2;
+
+/// Registry of the objects managed by the collector, with its statistics.
+pub struct Heap {
+    objects: Vec<Object>,
+    // Approximate bytes of the registered objects, live or allocated since the last collection
+    bytes: usize,
+    min_threshold: usize,
+    growth_factor: usize,
+    stats: GcStats,
+}
+
+/// Allocation and collection statistics of a [`Heap`].
+///
+/// Sizes are approximate, counting the size of each object but not of the values it holds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// Number of objects allocated.
+    pub allocations: usize,
+    /// Bytes allocated for objects.
+    pub bytes_allocated: usize,
+    /// Number of collections run.
+    pub collections: usize,
+    /// Number of unreachable objects freed by collections.
+    pub objects_collected: usize,
+    /// Number of objects still live after the last collection.
+    pub live_objects: usize,
+    /// Bytes of the objects still live after the last collection.
+    pub live_bytes: usize,
+    /// Bytes allocated at which the next collection is triggered.
+    pub threshold: usize,
+    /// Number of strings still interned after the last collection.
+    pub strings: usize,
+}
+
+// Weak reference to a registered object with its size
+struct Object {
+    kind: WeakObject,
+    size: usize,
+}
+
+enum WeakObject {
+    Scope(Weak<RefCell<Scope>>),
+    Instance(Weak<LoxInstance>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+}
+
+enum StrongObject {
+    Scope(ScopeRef),
+    Instance(Rc<LoxInstance>),
+    Upvalue(UpvalueRef),
+}
+
+/// Marks the objects reachable from the roots of a collection.
+pub struct Tracer {
+    // Addresses of the objects marked so far
+    marked: HashSet<usize>,
+    // Objects marked whose references have not been traced yet
+    gray: Vec<Gray>,
+}
+
+enum Gray {
+    Value(LoxValue),
+    Scope(ScopeRef),
+    Upvalue(UpvalueRef),
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            objects: Vec::new(),
+            bytes: 0,
+            min_threshold: MIN_THRESHOLD,
+            growth_factor: GROWTH_FACTOR,
+            stats: GcStats {
+                threshold: MIN_THRESHOLD,
+                ..GcStats::default()
+            },
+        }
+    }
+
+    /// Sets the minimum threshold of bytes allocated which triggers a collection, and the factor
+    /// the bytes live after a collection are multiplied by to get the next threshold. A threshold
+    /// of zero collects garbage before every statement or instruction.
+    pub fn configure(&mut self, min_threshold: usize, growth_factor: usize) {
+        self.min_threshold = min_threshold;
+        self.growth_factor = growth_factor;
+        self.stats.threshold = self.next_threshold(self.stats.live_bytes);
+    }
+
+    /// Returns the allocation and collection statistics.
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Returns whether enough bytes were allocated since the last collection to run another one.
+    pub fn should_collect(&self) -> bool {
+        self.bytes >= self.stats.threshold
+    }
+
+    pub(crate) fn register_scope(&mut self, scope: &ScopeRef) {
+        self.register(
+            WeakObject::Scope(Rc::downgrade(scope)),
+            mem::size_of::<Scope>(),
+        );
+    }
+
+    pub(crate) fn register_instance(&mut self, instance: &Rc<LoxInstance>) {
+        let size = mem::size_of::<LoxInstance>();
+        self.register(WeakObject::Instance(Rc::downgrade(instance)), size);
+    }
+
+    pub(crate) fn register_upvalue(&mut self, upvalue: &UpvalueRef) {
+        let size = mem::size_of::<Upvalue>();
+        self.register(WeakObject::Upvalue(Rc::downgrade(upvalue)), size);
+    }
+
+    fn register(&mut self, kind: WeakObject, size: usize) {
+        self.objects.push(Object { kind, size });
+        self.bytes += size;
+        self.stats.allocations += 1;
+        self.stats.bytes_allocated += size;
+    }
+
+    /// Sweeps the registered objects which were not marked by a tracer, clearing their contents.
+    pub(crate) fn sweep(&mut self, mut tracer: Tracer) {
+        tracer.trace();
+        let mut live_bytes = 0;
+        let mut unreachable = Vec::new();
+        // Objects which were already freed by reference counting are dropped from the registry
+        self.objects.retain(|object| match object.kind.upgrade() {
+            Some(strong) if tracer.marked.contains(&strong.address()) => {
+                live_bytes += object.size;
+                true
+            }
+            Some(strong) => {
+                unreachable.push(strong);
+                false
+            }
+            None => false,
+        });
+        self.stats.collections += 1;
+        self.stats.objects_collected += unreachable.len();
+        self.stats.live_objects = self.objects.len();
+        self.stats.live_bytes = live_bytes;
+        self.stats.threshold = self.next_threshold(live_bytes);
+        self.bytes = live_bytes;
+        // Every object in an unreachable cycle is cleared, so the whole cycle is freed once the
+        // strong references taken by the sweep are dropped
+        for object in &unreachable {
+            object.clear();
+        }
+        drop(unreachable);
+        self.stats.strings = symbol::prune();
+    }
+
+    fn next_threshold(&self, live_bytes: usize) -> usize {
+        (live_bytes * self.growth_factor).max(self.min_threshold)
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
+
+impl WeakObject {
+    fn upgrade(&self) -> Option<StrongObject> {
+        match self {
+            WeakObject::Scope(scope) => scope.upgrade().map(StrongObject::Scope),
+            WeakObject::Instance(instance) => instance.upgrade().map(StrongObject::Instance),
+            WeakObject::Upvalue(upvalue) => upvalue.upgrade().map(StrongObject::Upvalue),
+        }
+    }
+}
+
+impl StrongObject {
+    fn address(&self) -> usize {
+        match self {
+            StrongObject::Scope(scope) => address(scope),
+            StrongObject::Instance(instance) => address(instance),
+            StrongObject::Upvalue(upvalue) => address(upvalue),
+        }
+    }
+
+    // Drops the references held by the object. The contents are taken out before they are
+    // dropped, so no borrow of the object is held while other objects are freed
+    fn clear(&self) {
+        match self {
+            StrongObject::Scope(scope) => drop(scope.borrow_mut().take_values()),
+            StrongObject::Instance(instance) => drop(instance.take_fields()),
+            StrongObject::Upvalue(upvalue) => drop(mem::replace(
+                &mut *upvalue.borrow_mut(),
+                Upvalue::Closed(LoxValue::Nil),
+            )),
+        }
+    }
+}
+
+impl Tracer {
+    pub(crate) fn new() -> Self {
+        Tracer {
+            marked: HashSet::new(),
+            gray: Vec::new(),
+        }
+    }
+
+    /// Marks a value and the objects it refers to.
+    pub(crate) fn value(&mut self, value: &LoxValue) {
+        let address = match value {
+            LoxValue::Function(function) => address(function),
+            LoxValue::Class(class) => address(class),
+            LoxValue::Instance(instance) => address(instance),
+            LoxValue::Nil | LoxValue::Bool(_) | LoxValue::Number(_) | LoxValue::String(_) => return,
+        };
+        if self.marked.insert(address) {
+            self.gray.push(Gray::Value(value.clone()));
+        }
+    }
+
+    pub(crate) fn scope(&mut self, scope: &ScopeRef) {
+        if self.marked.insert(address(scope)) {
+            self.gray.push(Gray::Scope(Rc::clone(scope)));
+        }
+    }
+
+    pub(crate) fn upvalue(&mut self, upvalue: &UpvalueRef) {
+        if self.marked.insert(address(upvalue)) {
+            self.gray.push(Gray::Upvalue(Rc::clone(upvalue)));
+        }
+    }
+
+    pub(crate) fn closure(&mut self, closure: &Closure) {
+        for upvalue in &closure.upvalues {
+            self.upvalue(upvalue);
+        }
+    }
+
+    // Marks everything reachable from the objects marked so far
+    fn trace(&mut self) {
+        while let Some(object) = self.gray.pop() {
+            match object {
+                Gray::Value(LoxValue::Function(function)) => function.trace(self),
+                Gray::Value(LoxValue::Class(class)) => class.trace(self),
+                Gray::Value(LoxValue::Instance(instance)) => instance.trace(self),
+                Gray::Value(_) => (),
+                Gray::Scope(scope) => scope.borrow().trace(self),
+                Gray::Upvalue(upvalue) => {
+                    if let Upvalue::Closed(value) = &*upvalue.borrow() {
+                        self.value(value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for GcStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} objects allocated ({} bytes), {} collections freed {} objects, \
+             {} objects live ({} bytes), {} strings interned, next collection at {} bytes",
+            self.allocations,
+            self.bytes_allocated,
+            self.collections,
+            self.objects_collected,
+            self.live_objects,
+            self.live_bytes,
+            self.strings,
+            self.threshold
+        )
+    }
+}
+
+fn address<T>(object: &Rc<T>) -> usize {
+    Rc::as_ptr(object) as *const () as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::{run_with, Backend, Options};
+
+    // Runs source code collecting garbage before every statement or instruction, returning the
+    // value of a global and the statistics of the collector
+    fn run_stressed(source: &str, backend: Backend, global: &str) -> (String, GcStats) {
+        let mut env = Environment::new();
+        env.heap_mut().configure(0, 0);
+        let options = Options {
+            backend,
+            ..Options::default()
+        };
+        run_with(source, &mut env, &options).unwrap();
+        let value = env.get_global(&global.into()).unwrap().to_string();
+        env.collect_if_needed();
+        (value, env.gc_stats())
+    }
+
+    #[test]
+    fn cycles() {
+        let source = "class Node {
+              init() { var node = this; fun get() { return node; } this.get = get; }
+            }
+            fun churn(n) {
+              for (var i = 0; i < n; i = i + 1) { var node = Node(); node.me = node; }
+              return n;
+            }
+            var a = churn(10);";
+        for backend in [Backend::Tree, Backend::Vm] {
+            let (value, stats) = run_stressed(source, backend, "a");
+            assert_eq!(value, "10");
+            assert!(stats.objects_collected >= 20, "{backend:?}: {stats:?}");
+            assert!(stats.live_objects <= 1, "{backend:?}: {stats:?}");
+        }
+    }
+
+    #[test]
+    fn temporaries() {
+        // Values being evaluated survive the collections run by the calls evaluated after them
+        let source = "class Box { init(value) { this.value = value; } }
+            fun churn() { var box = Box(nil); box.me = box; return 1; }
+            fun sum(a, b) { return a.value + b; }
+            var box = Box(1);
+            box.value = Box(2).value + churn();
+            var a = sum(Box(box.value), churn()) + Box(4).value;";
+        for backend in [Backend::Tree, Backend::Vm] {
+            assert_eq!(run_stressed(source, backend, "a").0, "8");
+        }
+    }
+
+    #[test]
+    fn threshold() {
+        let mut env = Environment::new();
+        env.heap_mut().configure(100, 4);
+        run_with(
+            "for (var i = 0; i < 100; i = i + 1) { var a = i; }",
+            &mut env,
+            &Options::default(),
+        )
+        .unwrap();
+        let stats = env.gc_stats();
+        assert_eq!(stats.allocations, 201);
+        assert!(stats.collections > 0);
+        assert_eq!(stats.threshold, (stats.live_bytes * 4).max(100));
+    }
+}
@@ -0,0 +1,213 @@
+//! Embeddable Lox interpreter.
+//!
+//! An [`Interpreter`] owns the [`Environment`] programs are executed in and the [`Options`] they are
+//! executed with, so a host program can run several programs sharing the same global variables.
+//! Values printed by programs are written to the output given to the interpreter, and every error
+//! is returned as [`Diagnostic`]s, which can be rendered with the [`render`](crate::render)
+//! module. The interpreter never writes to the standard output unless it is the output, and never
+//! exits the process.
+//!
+//! Values are reference-counted without synchronization, so an interpreter stays on the thread it
+//! was created on. Deeply recursive programs grow the native stack of that thread on the heap as
+//! needed, so they run on threads of any stack size.
+
+use std::io::Write;
+
+use crate::environment::Environment;
+use crate::expression::LoxValue;
+use crate::symbol::Symbol;
+use crate::{Diagnostic, Options};
+
+/// Interpreter executing programs in its own environment.
+pub struct Interpreter {
+    env: Environment,
+    options: Options,
+}
+
+impl Interpreter {
+    /// Creates an interpreter printing to the standard output, with the default options.
+    pub fn new() -> Self {
+        Interpreter {
+            env: Environment::new(),
+            options: Options::default(),
+        }
+    }
+
+    /// Creates an interpreter printing to a writer, with the default options.
+    pub fn with_output(output: impl Write + 'static) -> Self {
+        Interpreter {
+            env: Environment::with_output(output),
+            options: Options::default(),
+        }
+    }
+
+    /// Sets the backend and other settings programs are executed with. Syntax trees, disassembled
+    /// programs, traces and the statistics of the garbage collector are written to the output of
+    /// the interpreter if set, while the settings only used by the command line, such as the format
+    /// of errors, are ignored.
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Executes source code, returning the diagnostics if an error occurs. Variables declared by
+    /// the program remain defined for the next programs.
+    pub fn run(&mut self, source: &str) -> Result<(), Vec<Diagnostic>> {
+        crate::run_with(source, &mut self.env, &self.options)
+    }
+
+    /// Executes a syntax tree in JSON format, returning the diagnostics if an error occurs. The
+    /// spans of diagnostics loading the syntax tree refer to the JSON document.
+    pub fn run_ast(&mut self, json: &str) -> Result<(), Vec<Diagnostic>> {
+        crate::run_stmts(crate::parse_ast(json)?, "", &mut self.env, &self.options)
+    }
+
+    /// Returns the value of a global variable, `None` if it is not defined.
+    pub fn get(&self, name: &str) -> Option<LoxValue> {
+        self.env.get_global(&Symbol::intern(name))
+    }
+
+    /// Returns the environment programs are executed in.
+    pub fn environment(&self) -> &Environment {
+        &self.env
+    }
+
+    /// Returns the environment mutably, to define variables or configure the garbage collector.
+    pub fn environment_mut(&mut self) -> &mut Environment {
+        &mut self.env
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{Backend, ErrorKind};
+
+    // Output shared with the test, which reads it after the interpreter writes to it
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Buffer {
+        fn take(&self) -> String {
+            String::from_utf8(self.0.take()).unwrap()
+        }
+    }
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Output which always fails, like a closed connection
+    struct Closed;
+
+    impl Write for Closed {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let buffer = Buffer::default();
+            let options = Options {
+                backend,
+                ..Options::default()
+            };
+            let mut interpreter = Interpreter::with_output(buffer.clone()).with_options(options);
+            assert_eq!(interpreter.run("var a = 1; print a + 1;"), Ok(()));
+            assert_eq!(interpreter.run(r#"print "a" + "b"; print nil;"#), Ok(()));
+            assert_eq!(buffer.take(), "2\nab\nnil\n");
+            let errs = interpreter.run("print a; print c;").unwrap_err();
+            assert_eq!(errs[0].kind(), ErrorKind::UndefinedVariable);
+            assert_eq!(buffer.take(), "1\n");
+            assert_eq!(interpreter.get("a"), Some(LoxValue::Number(1.0)));
+        }
+    }
+
+    #[test]
+    fn dumps() {
+        let buffer = Buffer::default();
+        let options = Options {
+            dump_ast: Some(crate::printer::AstFormat::Sexpr),
+            skip_execution: true,
+            ..Options::default()
+        };
+        let mut interpreter = Interpreter::with_output(buffer.clone()).with_options(options);
+        assert_eq!(interpreter.run("print 1;"), Ok(()));
+        assert_eq!(buffer.take(), "(print 1)\n");
+        let options = Options {
+            gc_stats: true,
+            ..Options::default()
+        };
+        let mut interpreter = Interpreter::with_output(buffer.clone()).with_options(options);
+        assert!(interpreter.run("print 1;").is_ok());
+        let output = buffer.take();
+        assert!(output.starts_with("1\ngc: "), "{output}");
+        assert!(interpreter.run("print;").is_err());
+        assert_eq!(buffer.take(), "");
+    }
+
+    #[test]
+    fn trace() {
+        let buffer = Buffer::default();
+        let options = Options {
+            backend: Backend::Vm,
+            trace: true,
+            ..Options::default()
+        };
+        let mut interpreter =
+            Interpreter::with_output(buffer.clone()).with_options(options.clone());
+        assert_eq!(interpreter.run("print 1;"), Ok(()));
+        assert_eq!(
+            buffer.take(),
+            "          [ <fn script> ]\n\
+             0000    1 Constant           0 1\n\
+             \x20         [ <fn script> ][ 1 ]\n\
+             0003    1 Print\n\
+             1\n\
+             \x20         [ <fn script> ]\n\
+             0004    1 Nil\n\
+             \x20         [ <fn script> ][ nil ]\n\
+             0005    1 Return\n"
+        );
+        let mut interpreter = Interpreter::with_output(Closed).with_options(options);
+        let errs = interpreter.run("print 1;").unwrap_err();
+        assert_eq!(errs[0].kind(), ErrorKind::OutputFailed);
+    }
+
+    #[test]
+    fn failed_output() {
+        for backend in [Backend::Tree, Backend::Vm] {
+            let options = Options {
+                backend,
+                ..Options::default()
+            };
+            let mut interpreter = Interpreter::with_output(Closed).with_options(options);
+            let source = "var a = 1;\nprint a;";
+            let errs = interpreter.run(source).unwrap_err();
+            assert_eq!(errs[0].kind(), ErrorKind::OutputFailed);
+            assert_eq!(errs[0].span().start_location(source).line, 2);
+        }
+    }
+}
@@ -1,55 +1,133 @@
+pub mod chunk;
+pub mod class;
+pub mod compiler;
+pub mod diagnostic;
+pub mod disassembler;
+pub mod environment;
 pub mod expression;
+pub mod function;
+pub mod gc;
+pub mod interpreter;
+pub mod optimizer;
 pub mod peg_parser;
+pub mod printer;
+pub mod render;
+pub mod resolver;
+pub mod span;
 pub mod statement;
+pub mod symbol;
+pub mod value;
+pub mod vm;
 
-use peg_parser::lox_parser;
+pub use diagnostic::{Diagnostic, ErrorKind, Severity};
+pub use interpreter::Interpreter;
+
+use environment::Environment;
+use printer::AstFormat;
+use render::Report;
+use span::Span;
+use statement::Stmt;
+use vm::Vm;
 
 use std::fs;
 use std::io;
-use std::io::Write;
-use std::process;
-
-// TODO: Reconsider the types of errors
-#[derive(Debug, PartialEq)]
-pub enum Diagnostic {
-    LoxError {
-        line: usize,
-        message: String,
-    },
-    ParseError {
-        error: peg::error::ParseError<<str as peg::Parse>::PositionRepr>,
-    },
+use std::io::{IsTerminal, Write};
+
+/// Options of the interpreter, set from the command line.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub error_format: ErrorFormat,
+    /// Format to dump the syntax tree of programs in to the standard output, if any.
+    pub dump_ast: Option<AstFormat>,
+    /// Whether to stop after programs are checked, without executing them.
+    pub skip_execution: bool,
+    /// Whether scripts are syntax trees in JSON format instead of source code.
+    pub ast_input: bool,
+    pub backend: Backend,
+    pub opt_level: OptLevel,
+    /// Whether to print the bytecode programs are compiled to to the standard output.
+    pub disassemble: bool,
+    /// Whether to trace every instruction executed by the virtual machine to the standard output.
+    pub trace: bool,
+    /// Whether to print the statistics of the garbage collector to the standard output after a
+    /// program is executed.
+    pub gc_stats: bool,
 }
 
-/// Loads a file and executes it.
+/// Backend programs are executed with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Tree-walking interpreter evaluating the syntax tree directly.
+    #[default]
+    Tree,
+    /// Virtual machine executing the program compiled to bytecode.
+    Vm,
+}
+
+/// Optimizations applied to programs before they are executed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OptLevel {
+    /// Programs are executed as they are written.
+    O0,
+    /// Constant expressions are folded by the [`optimizer`].
+    #[default]
+    O1,
+}
+
+/// Format diagnostics are printed in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Human readable diagnostics with source code snippets.
+    #[default]
+    Human,
+    /// One JSON object per diagnostic per line.
+    Json,
+}
+
+/// Loads a file and executes it, either as source code or as a syntax tree in JSON format.
+///
+/// If an error occurs in the program, its diagnostics are printed to the standard error. Returns
+/// the exit status of the interpreter: 0 on success, 65 if the program is invalid and 70 if an
+/// error occurs while it is executed, as in `sysexits.h`.
 ///
-/// If an I/O error is occured it returns the error and terminates early.
-/// If an error is occured in the users program, it prints the diagnostic and terminates.
-pub fn run_file(path: &str) -> io::Result<()> {
+/// # Errors
+///
+/// Returns the error if the file cannot be read.
+pub fn run_file(path: &str, options: &Options) -> io::Result<i32> {
     let file = fs::read_to_string(path)?;
-    if let Err(err) = run(&file) {
-        let exit_code = match &err {
-            Diagnostic::LoxError {
-                line: _,
-                message: _,
-            } => 70, // EX_SOFTWARE
-            Diagnostic::ParseError { error: _ } => 65, // EX_DATAERR
-        };
-        error(err);
-        process::exit(exit_code);
+    let mut env = Environment::new();
+    // Diagnostics with the source code their spans refer to, which is unknown for errors in a
+    // syntax tree loaded from JSON
+    let result = if options.ast_input {
+        match parse_ast(&file) {
+            Ok(stmts) => run_stmts(stmts, "", &mut env, options).map_err(|errs| (errs, "")),
+            Err(errs) => Err((errs, file.as_str())),
+        }
+    } else {
+        run_with(&file, &mut env, options).map_err(|errs| (errs, file.as_str()))
+    };
+    let Err((errs, source)) = result else {
+        return Ok(0);
+    };
+    errs.iter()
+        .for_each(|err| error(err, path, source, options.error_format));
+    match errs.first() {
+        Some(Diagnostic::LoxError { .. }) => Ok(70), // EX_SOFTWARE
+        _ => Ok(65),                                 // EX_DATAERR
     }
-    Ok(())
 }
 
 /// Starts a prompt, accepting input from the user and executing the code when a newline occurs.
 ///
 /// The prompt can be exited with `Ctrl-D`.
-/// If an error occurs the diagnostic is printed to the user and execution continues.
+/// If an error occurs the diagnostics are printed to the user and execution continues.
+/// Variables declared on a line remain defined for the rest of the session.
 ///
 /// # Errors
 ///
 /// This function returns a [`std::io::Result`], terminating early if an I/O error occurs.
-pub fn run_prompt() -> io::Result<()> {
+pub fn run_prompt(options: &Options) -> io::Result<()> {
+    let mut env = Environment::new();
     loop {
         print!("> ");
         io::stdout().flush()?;
@@ -59,58 +137,412 @@ pub fn run_prompt() -> io::Result<()> {
             println!();
             break Ok(());
         };
-        match run(&line) {
+        match run_with(&line, &mut env, options) {
             Ok(()) => (),
-            Err(err) => error(err),
+            Err(errs) => errs
+                .iter()
+                .for_each(|err| error(err, "<prompt>", &line, options.error_format)),
         }
     }
 }
 
-/// Executes the source code and returns a diagnostic if an error occurs.
-pub fn run(source: &str) -> Result<(), Diagnostic> {
-    let stmts = lox_parser::program(source)?;
-    for stmt in &stmts {
-        stmt.print();
+/// Executes the source code in an [`Environment`] and returns the diagnostics if an error occurs.
+///
+/// Every syntax error in the program is reported together. The program is resolved before it is
+/// executed, so no part of it is executed if it is invalid.
+pub fn run(source: &str, env: &mut Environment) -> Result<(), Vec<Diagnostic>> {
+    run_with(source, env, &Options::default())
+}
+
+/// Parses source code into a list of statements without resolving or executing it, returning
+/// every syntax error in the source code if it is invalid.
+pub fn parse(source: &str) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    peg_parser::parse(source)
+}
+
+/// Loads a list of statements from a syntax tree in JSON format, as dumped with
+/// [`AstFormat::Json`]. Spans are optional, so syntax trees can be generated without source code.
+///
+/// [`Diagnostic::AstError`] is returned if the JSON document is not a valid syntax tree.
+pub fn parse_ast(json: &str) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    serde_json::from_str(json).map_err(|err| {
+        // Errors are located by line and column in the document
+        let offset = json
+            .split_inclusive('\n')
+            .take(err.line().saturating_sub(1))
+            .map(str::len)
+            .sum::<usize>()
+            + err.column().saturating_sub(1);
+        let message = err.to_string();
+        let message = message
+            .rsplit_once(" at line ")
+            .map_or(&*message, |(message, _)| message);
+        vec![Diagnostic::AstError {
+            span: Span::new(offset, offset + 1),
+            message: format!("invalid syntax tree: {message}"),
+        }]
+    })
+}
+
+/// Executes a syntax tree in JSON format in an [`Environment`] and returns the diagnostics if an
+/// error occurs, like [`run`].
+pub fn run_ast(json: &str, env: &mut Environment) -> Result<(), Vec<Diagnostic>> {
+    run_stmts(parse_ast(json)?, "", env, &Options::default())
+}
+
+/// Executes the source code in an [`Environment`] like [`run`], with the backend and other
+/// settings of the options.
+pub fn run_with(
+    source: &str,
+    env: &mut Environment,
+    options: &Options,
+) -> Result<(), Vec<Diagnostic>> {
+    run_stmts(parse(source)?, source, env, options)
+}
+
+/// Resolves and executes a list of statements in an [`Environment`] like [`run_with`], so a
+/// program can be parsed once and executed many times.
+///
+/// `source` is the source code the statements were parsed from, used to locate instructions when
+/// tracing or disassembling. It is empty for syntax trees loaded from JSON.
+pub fn run_stmts(
+    stmts: Vec<Stmt>,
+    source: &str,
+    env: &mut Environment,
+    options: &Options,
+) -> Result<(), Vec<Diagnostic>> {
+    if let Some(format) = options.dump_ast {
+        let dump = printer::dump(&stmts, format);
+        writeln!(env.output(), "{}", dump.trim_end()).map_err(output_failed)?;
     }
-    for stmt in stmts {
-        stmt.execute()?
+    resolver::resolve(&stmts)?;
+    let stmts = match options.opt_level {
+        OptLevel::O0 => stmts,
+        OptLevel::O1 => optimizer::optimize(stmts),
+    };
+    // Programs are compiled once, to be both disassembled and executed
+    let script = if options.disassemble || options.backend == Backend::Vm {
+        Some(compiler::compile(&stmts).map_err(|err| vec![err])?)
+    } else {
+        None
+    };
+    if let Some(script) = script.as_ref().filter(|_| options.disassemble) {
+        let listing = disassembler::disassemble(script, source);
+        writeln!(env.output(), "{}", listing.trim_end()).map_err(output_failed)?;
     }
-    Ok(())
+    if options.skip_execution {
+        return Ok(());
+    }
+    let result = match script {
+        Some(script) if options.backend == Backend::Vm => {
+            let mut vm = Vm::new(env);
+            if options.trace {
+                vm = vm.with_trace(source);
+            }
+            vm.run(script)
+        }
+        _ => stmts
+            .iter()
+            .try_for_each(|stmt| stmt.execute(env).map(drop)),
+    };
+    if options.gc_stats {
+        let stats = env.gc_stats();
+        writeln!(env.output(), "gc: {stats}").map_err(output_failed)?;
+    }
+    result.map_err(|err| vec![err])
 }
 
-impl From<peg::error::ParseError<<str as peg::Parse>::PositionRepr>> for Diagnostic {
-    // TODO: Audit
-    fn from(value: peg::error::ParseError<<str as peg::Parse>::PositionRepr>) -> Diagnostic {
-        Diagnostic::ParseError { error: value }
-    }
+// Returns the error for a dump which could not be written to the output, which is not located in
+// the program
+fn output_failed(err: io::Error) -> Vec<Diagnostic> {
+    vec![environment::output_failed(&err, Span::default())]
 }
 
-// TODO: Consider consolididating error and report as an implementation of `std::fmt::Display`
-// TODO: Remove report
-/// Prints a diagnostic to the standard error.
+/// Prints a diagnostic to the standard error in the given format, locating it in the source code
+/// of the file it was reported in. Human readable diagnostics are colored if the standard error is
+/// a terminal.
 ///
 /// # Panics
 ///
 /// Panics if writting to [`std::io::stderr`] fails.
-pub fn error(diagnostic: Diagnostic) {
-    match diagnostic {
-        Diagnostic::LoxError { line, message } => report(line, "", &message),
-        Diagnostic::ParseError { error } => eprintln!("Parse Error: {error}"),
-    }
-}
-
-// Prints a formated diagnostic to standard error.
-fn report(line: usize, err_where: &str, message: &str) {
-    eprintln!("[Line: {line}] Error{err_where}: {message}");
+pub fn error(diagnostic: &Diagnostic, file: &str, source: &str, format: ErrorFormat) {
+    let stderr = io::stderr();
+    let result = match format {
+        ErrorFormat::Human => {
+            let color = stderr.is_terminal();
+            Report::from(diagnostic).render(&mut stderr.lock(), file, source, color)
+        }
+        ErrorFormat::Json => render::render_json(diagnostic, &mut stderr.lock(), file, source),
+    };
+    result.expect("Writing to stderr should not fail");
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use expression::LoxValue;
 
-    // #[test]
-    // fn it_works() {
-    //     let result = add(2, 2);
-    //     assert_eq!(result, 4);
-    // }
+    #[test]
+    fn global_variables() {
+        let mut env = Environment::new();
+        assert_eq!(run("var a = 1; var b = a + 2; var c;", &mut env), Ok(()));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Number(1.0)));
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Number(3.0)));
+        assert_eq!(env.get_global(&"c".into()), Some(LoxValue::Nil));
+        assert!(run("print d;", &mut env).is_err());
+    }
+
+    #[test]
+    fn block_scopes() {
+        let mut env = Environment::new();
+        let source = "var a = 1; var b = 1; { var a = 2; b = a; var c = 3; }";
+        assert_eq!(run(source, &mut env), Ok(()));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Number(1.0)));
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Number(2.0)));
+        assert!(env.get_global(&"c".into()).is_none());
+    }
+
+    #[test]
+    fn if_else() {
+        let mut env = Environment::new();
+        let source = "var a; var b; var c;
+            if (nil) a = 1; else a = 2;
+            if (0) b = 1; else b = 2;
+            if (true) if (false) c = 1; else c = 2;";
+        assert_eq!(run(source, &mut env), Ok(()));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Number(2.0)));
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Number(1.0)));
+        assert_eq!(env.get_global(&"c".into()), Some(LoxValue::Number(2.0)));
+    }
+
+    #[test]
+    fn loops() {
+        let mut env = Environment::new();
+        let source = "var a = 0; var b = 0;
+            while (a < 5) a = a + 1;
+            for (var i = 0; i < 5; i = i + 1) b = b + i;";
+        assert_eq!(run(source, &mut env), Ok(()));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Number(5.0)));
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Number(10.0)));
+        assert!(env.get_global(&"i".into()).is_none());
+    }
+
+    #[test]
+    fn logical() {
+        let mut env = Environment::new();
+        let source = r#"var called = false;
+            var a = nil or "default";
+            var b = 1 and 2;
+            var c = false and (called = true);
+            var d = true or (called = true);"#;
+        assert_eq!(run(source, &mut env), Ok(()));
+        assert_eq!(
+            env.get_global(&"a".into()),
+            Some(LoxValue::String("default".into()))
+        );
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Number(2.0)));
+        assert_eq!(env.get_global(&"c".into()), Some(LoxValue::Bool(false)));
+        assert_eq!(env.get_global(&"d".into()), Some(LoxValue::Bool(true)));
+        assert_eq!(
+            env.get_global(&"called".into()),
+            Some(LoxValue::Bool(false))
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let mut env = Environment::new();
+        let source = "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            fun noop() {}
+            var a = fib(10);
+            var b = noop();";
+        assert_eq!(run(source, &mut env), Ok(()));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Number(55.0)));
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Nil));
+        assert!(run("fib(1, 2);", &mut env).is_err());
+        assert!(run("a();", &mut env).is_err());
+        assert!(run("fun f() { f(); } f();", &mut Environment::new()).is_err());
+    }
+
+    #[test]
+    fn recursion() {
+        // Test threads have a small stack, which the interpreter grows as needed
+        let source = "fun sum(n) { if (n == 0) return 0; return n + sum(n - 1); }
+            class Countdown { init(n) { if (n > 0) Countdown(n - 1); } }
+            var a = sum(9000); Countdown(9000);";
+        for backend in [Backend::Tree, Backend::Vm] {
+            let options = Options {
+                backend,
+                ..Options::default()
+            };
+            let mut env = Environment::new();
+            assert_eq!(run_with(source, &mut env, &options), Ok(()));
+            assert_eq!(
+                env.get_global(&"a".into()),
+                Some(LoxValue::Number(40504500.0))
+            );
+        }
+    }
+
+    #[test]
+    fn closures() {
+        let mut env = Environment::new();
+        let source =
+            "fun counter() { var i = 0; fun count() { i = i + 1; return i; } return count; }
+            var c1 = counter(); var c2 = counter();
+            c1(); c1();
+            var a = c1(); var b = c2();
+            fun adder(x) { fun add(y) { return x + y; } return add; }
+            var c = adder(1)(2);";
+        assert_eq!(run(source, &mut env), Ok(()));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Number(3.0)));
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Number(1.0)));
+        assert_eq!(env.get_global(&"c".into()), Some(LoxValue::Number(3.0)));
+    }
+
+    #[test]
+    fn closures_resolve_statically() {
+        // The variable declared after the closure shadows the global only for later code
+        let source = r#"var a = "global"; var b; var c;
+            { fun show() { return a; } b = show(); var a = "block"; c = show(); }"#;
+        for backend in [Backend::Tree, Backend::Vm] {
+            let mut env = Environment::new();
+            let options = Options {
+                backend,
+                ..Options::default()
+            };
+            assert_eq!(run_with(source, &mut env, &options), Ok(()));
+            let global = Some(LoxValue::String("global".into()));
+            assert_eq!(env.get_global(&"b".into()), global);
+            assert_eq!(env.get_global(&"c".into()), global);
+        }
+    }
+
+    #[test]
+    fn classes() {
+        let mut env = Environment::new();
+        let source = "class Point {
+                init(x, y) { this.x = x; this.y = y; }
+                sum() { return this.x + this.y; }
+                scale(k) { this.x = this.x * k; this.y = this.y * k; return this; }
+            }
+            var p = Point(1, 2);
+            var a = p.sum();
+            var b = p.scale(2).sum();
+            var method = p.sum;
+            p.x = 10;
+            var c = method();
+            var d = p.init(0, 0) == p;";
+        assert_eq!(run(source, &mut env), Ok(()));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Number(3.0)));
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Number(6.0)));
+        assert_eq!(env.get_global(&"c".into()), Some(LoxValue::Number(14.0)));
+        assert_eq!(env.get_global(&"d".into()), Some(LoxValue::Bool(true)));
+        assert!(run("p.z;", &mut env).is_err());
+        assert!(run("Point(1);", &mut env).is_err());
+        assert!(run("a.x = 1;", &mut env).is_err());
+    }
+
+    #[test]
+    fn inheritance() {
+        let mut env = Environment::new();
+        let source = r#"class A {
+                init(name) { this.name = name; }
+                greet() { return "A " + this.name; }
+                kind() { return "A"; }
+            }
+            class B < A {
+                greet() { return "B " + super.greet(); }
+            }
+            class C < B {
+                init() { super.init("c"); }
+                greet() { return "C " + super.greet(); }
+            }
+            var a = C().greet();
+            var b = C().kind();"#;
+        assert_eq!(run(source, &mut env), Ok(()));
+        assert_eq!(
+            env.get_global(&"a".into()),
+            Some(LoxValue::String("C B A c".into()))
+        );
+        assert_eq!(
+            env.get_global(&"b".into()),
+            Some(LoxValue::String("A".into()))
+        );
+        assert!(run("var D = 1; class E < D {}", &mut env).is_err());
+        assert!(run("class F { f() { return super.f(); } } F().f();", &mut env).is_err());
+        assert!(run(
+            "class G < A { f() { return super.missing(); } } G(1).f();",
+            &mut env
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn error_locations() {
+        let location = |source: &str| {
+            let errs = run(source, &mut Environment::new()).unwrap_err();
+            let location = errs[0].span().start_location(source);
+            (location.line, location.column)
+        };
+        assert_eq!(location("var a = 1;\nprint a + nil;"), (2, 9));
+        assert_eq!(location("print b;"), (1, 7));
+        assert_eq!(location("fun f(a) {}\n  f();"), (2, 3));
+        assert_eq!(location("class A {}\nA().x;"), (2, 1));
+        assert_eq!(location("var a;\n{\n  var a = 1;\n  var a = 2;\n}"), (4, 7));
+        assert_eq!(location("fun f() {}\nreturn f();"), (2, 1));
+    }
+
+    #[test]
+    fn error_kinds() {
+        let kind = |source: &str| run(source, &mut Environment::new()).unwrap_err()[0].kind();
+        assert_eq!(kind("print a;"), ErrorKind::UndefinedVariable);
+        assert_eq!(kind("print 1 - \"a\";"), ErrorKind::InvalidOperand);
+        assert_eq!(kind("1();"), ErrorKind::NotCallable);
+        assert_eq!(kind("fun f() {} f(1);"), ErrorKind::ArityMismatch);
+        assert_eq!(kind("class A {} A().b;"), ErrorKind::UndefinedProperty);
+        assert_eq!(kind("true.b = 2;"), ErrorKind::NotAnInstance);
+        assert_eq!(
+            kind("var A = 1; class B < A {}"),
+            ErrorKind::InvalidSuperclass
+        );
+        assert_eq!(
+            run("fun f() { f(); } f();", &mut Environment::new()).unwrap_err()[0].kind(),
+            ErrorKind::StackOverflow
+        );
+        assert_eq!(kind("return;"), ErrorKind::TopLevelReturn);
+        assert_eq!(kind("print this;"), ErrorKind::InvalidThis);
+        assert_eq!(kind("print 1"), ErrorKind::Syntax);
+        assert_eq!(kind("1 + 2 = 3;"), ErrorKind::InvalidAssignment);
+    }
+
+    #[test]
+    fn json_ast() {
+        let source = "class A { init(x) { this.x = x; } }
+            fun f(a) { for (var i = 0; i < 3; i = i + 1) a = a * 2; return a; }
+            var a = !(A(f(1)).x == 8) and -1;";
+        let json = printer::dump(&parse(source).unwrap(), AstFormat::Json);
+        let stmts = parse_ast(&json).unwrap();
+        assert_eq!(
+            printer::dump(&stmts, AstFormat::Sexpr),
+            printer::dump(&parse(source).unwrap(), AstFormat::Sexpr)
+        );
+        assert_eq!(printer::dump(&stmts, AstFormat::Json), json);
+
+        let mut env = Environment::new();
+        assert_eq!(run_ast(&json, &mut env), Ok(()));
+        assert_eq!(env.get_global(&"a".into()), Some(LoxValue::Bool(false)));
+        let json = r#"[{"Var/v1": {"name": "b", "initializer": {"Literal/v1": {"value": {"Number": 2}}}}}]"#;
+        assert_eq!(run_ast(json, &mut env), Ok(()));
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Number(2.0)));
+    }
+
+    #[test]
+    fn invalid_json_ast() {
+        let json = "[\n  {\"Var/v0\": {\"name\": \"a\"}}\n]";
+        let errs = run_ast(json, &mut Environment::new()).unwrap_err();
+        assert_eq!(errs[0].kind(), ErrorKind::InvalidAst);
+        assert_eq!(errs[0].span().start_location(json).line, 2);
+        assert!(errs[0].message().contains("unknown variant `Var/v0`"));
+    }
 }
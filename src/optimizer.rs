@@ -0,0 +1,212 @@
+//! Lox optimizer.
+//!
+//! The optimizer is a pass run on a program after it is resolved and before it is executed. It
+//! folds operators whose operands are all literals into a single literal, so `1 + 2 * 3` becomes
+//! `7` and `!true` becomes `false`, and replaces logical operators with a literal left operand by
+//! the operand they evaluate to.
+//!
+//! Operators are applied with the same functions as at runtime. An operation which fails, such as
+//! `1 + "a"`, is left in the program so its error is still reported when it is executed, at its
+//! original location. Operands which are not literals are never simplified, since their types are
+//! only known at runtime.
+
+use std::rc::Rc;
+
+use crate::expression::{Binary, Expr, Literal, LiteralValue, Logical, LogicalOp, LoxValue, Unary};
+use crate::statement::{Function, Stmt};
+use crate::symbol::Symbol;
+
+/// Optimizes a resolved program, returning the statements with their constant expressions folded.
+pub fn optimize(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.into_iter().map(fold_stmt).collect()
+}
+
+fn fold_stmt(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Block(stmts) => Stmt::Block(optimize(stmts)),
+        Stmt::Class(mut stmt) => {
+            stmt.superclass = stmt.superclass.map(fold_expr);
+            stmt.methods = stmt.methods.into_iter().map(fold_function).collect();
+            Stmt::Class(stmt)
+        }
+        Stmt::Expression(expr) => Stmt::Expression(fold_expr(expr)),
+        Stmt::Function(stmt) => Stmt::Function(fold_function(stmt)),
+        Stmt::If(mut stmt) => {
+            stmt.condition = fold_expr(stmt.condition);
+            stmt.then_branch = Box::new(fold_stmt(*stmt.then_branch));
+            stmt.else_branch = stmt.else_branch.map(|stmt| Box::new(fold_stmt(*stmt)));
+            Stmt::If(stmt)
+        }
+        Stmt::Print(expr) => Stmt::Print(fold_expr(expr)),
+        Stmt::Return(mut stmt) => {
+            stmt.value = stmt.value.map(fold_expr);
+            Stmt::Return(stmt)
+        }
+        Stmt::Var(mut stmt) => {
+            stmt.initializer = stmt.initializer.map(fold_expr);
+            Stmt::Var(stmt)
+        }
+        Stmt::While(mut stmt) => {
+            stmt.condition = fold_expr(stmt.condition);
+            stmt.body = Box::new(fold_stmt(*stmt.body));
+            Stmt::While(stmt)
+        }
+    }
+}
+
+// Declarations are shared with the functions created from them, so a declaration which is already
+// shared is left as is
+fn fold_function(function: Rc<Function>) -> Rc<Function> {
+    match Rc::try_unwrap(function) {
+        Ok(mut function) => {
+            function.body = optimize(function.body);
+            Rc::new(function)
+        }
+        Err(function) => function,
+    }
+}
+
+fn fold_expr(expr: Expr) -> Expr {
+    match expr {
+        Expr::Assign(mut expr) => {
+            expr.value = fold_boxed(expr.value);
+            Expr::Assign(expr)
+        }
+        Expr::Binary(expr) => fold_binary(expr),
+        Expr::Call(mut expr) => {
+            expr.callee = fold_boxed(expr.callee);
+            expr.arguments = expr.arguments.into_iter().map(fold_expr).collect();
+            Expr::Call(expr)
+        }
+        Expr::Get(mut expr) => {
+            expr.object = fold_boxed(expr.object);
+            Expr::Get(expr)
+        }
+        // Groupings of literals are removed so the enclosing expression can be folded
+        Expr::Gropuping(mut expr) => match fold_expr(*expr.expression) {
+            Expr::Literal(literal) => Expr::Literal(Literal::new(literal.value, expr.span)),
+            folded => {
+                expr.expression = Box::new(folded);
+                Expr::Gropuping(expr)
+            }
+        },
+        Expr::Logical(expr) => fold_logical(expr),
+        Expr::Set(mut expr) => {
+            expr.object = fold_boxed(expr.object);
+            expr.value = fold_boxed(expr.value);
+            Expr::Set(expr)
+        }
+        Expr::Unary(expr) => fold_unary(expr),
+        expr @ (Expr::Literal(_) | Expr::Super(_) | Expr::This(_) | Expr::Variable(_)) => expr,
+    }
+}
+
+// Folds an operand in place, reusing its allocation
+fn fold_boxed(mut expr: Box<Expr>) -> Box<Expr> {
+    *expr = fold_expr(*expr);
+    expr
+}
+
+fn fold_binary(mut expr: Binary) -> Expr {
+    expr.left = fold_boxed(expr.left);
+    expr.right = fold_boxed(expr.right);
+    let (Some(left), Some(right)) = (constant(&expr.left), constant(&expr.right)) else {
+        return Expr::Binary(expr);
+    };
+    match expr.operate(left, right).ok().and_then(literal) {
+        Some(value) => Expr::Literal(Literal::new(value, expr.span)),
+        None => Expr::Binary(expr),
+    }
+}
+
+fn fold_unary(mut expr: Unary) -> Expr {
+    expr.operand = fold_boxed(expr.operand);
+    let Some(operand) = constant(&expr.operand) else {
+        return Expr::Unary(expr);
+    };
+    match expr.operate(operand).ok().and_then(literal) {
+        Some(value) => Expr::Literal(Literal::new(value, expr.span)),
+        None => Expr::Unary(expr),
+    }
+}
+
+// The right operand is never evaluated if the left operand short-circuits the operator, and is
+// the value of the expression otherwise
+fn fold_logical(mut expr: Logical) -> Expr {
+    expr.left = fold_boxed(expr.left);
+    expr.right = fold_boxed(expr.right);
+    let Some(left) = constant(&expr.left) else {
+        return Expr::Logical(expr);
+    };
+    match (&expr.operator, left.is_truthy()) {
+        (LogicalOp::Or, true) | (LogicalOp::And, false) => *expr.left,
+        _ => *expr.right,
+    }
+}
+
+// Returns the value of an expression if it is a literal
+fn constant(expr: &Expr) -> Option<LoxValue> {
+    match expr {
+        Expr::Literal(literal) => literal.eval().ok(),
+        _ => None,
+    }
+}
+
+// Converts a value back to a literal, `None` for values which cannot be written as literals
+fn literal(value: LoxValue) -> Option<LiteralValue> {
+    match value {
+        LoxValue::Nil => Some(LiteralValue::Nil),
+        LoxValue::Bool(value) => Some(LiteralValue::Bool(value)),
+        LoxValue::Number(value) => Some(LiteralValue::Number(value)),
+        LoxValue::String(value) => Some(LiteralValue::String(Symbol::intern(&value))),
+        LoxValue::Function(_) | LoxValue::Class(_) | LoxValue::Instance(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::printer::{self, AstFormat};
+    use crate::{ErrorKind, OptLevel, Options};
+
+    fn fold(source: &str) -> String {
+        let stmts = optimize(crate::parse(source).unwrap());
+        printer::dump(&stmts, AstFormat::Sexpr)
+            .trim_end()
+            .to_string()
+    }
+
+    #[test]
+    fn folding() {
+        assert_eq!(fold("print 1 + 2 * 3;"), "(print 7)");
+        assert_eq!(fold("print !true;"), "(print false)");
+        assert_eq!(fold("print -(4 - 6) / 2 >= 1;"), "(print true)");
+        assert_eq!(fold(r#"print "a" + "b" == "ab";"#), "(print true)");
+        assert_eq!(fold("print nil == false;"), "(print false)");
+        assert_eq!(fold("print a + 1 * 2;"), "(print (+ a 2))");
+        assert_eq!(fold("print nil or a;"), "(print a)");
+        assert_eq!(fold("print 1 and a;"), "(print a)");
+        assert_eq!(fold("print false and a;"), "(print false)");
+        assert_eq!(
+            fold("fun f() { return 2 * 3; }"),
+            fold("fun f() { return 6; }")
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert_eq!(fold(r#"print 1 + "a";"#), r#"(print (+ 1 "a"))"#);
+        assert_eq!(fold("print -nil + 1;"), "(print (+ (- nil) 1))");
+        let source = r#"print 2 * (1 + "a");"#;
+        for opt_level in [OptLevel::O0, OptLevel::O1] {
+            let options = Options {
+                opt_level,
+                ..Options::default()
+            };
+            let errs = crate::run_with(source, &mut Environment::new(), &options).unwrap_err();
+            assert_eq!(errs[0].kind(), ErrorKind::InvalidOperand);
+            assert_eq!(errs[0].span().start_location(source).column, 14);
+        }
+    }
+}
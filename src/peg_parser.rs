@@ -1,95 +1,345 @@
 use super::expression::*;
+use super::span::Span;
 use super::statement::*;
+use super::symbol::Symbol;
+use super::{Diagnostic, ErrorKind};
+
+use peg::error::ExpectedSet;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 // TODO: Add quiet! and expect! error messages for identifiers, etc.
 peg::parser! {
-    /// Parser for Lox language grammar, currently supporting expressions only
-    pub grammar lox_parser() for str {
+    /// Parser for Lox language grammar.
+    ///
+    /// Errors which do not stop parsing, such as assignments to invalid targets, are recorded in
+    /// `recovery` instead of failing the parse. Declarations of a program or a block which fail to
+    /// parse are skipped and recorded too, so the rest of the program is still parsed.
+    pub grammar lox_parser(recovery: &Recovery) for str {
+
+
+        pub rule program() -> Vec<Stmt> = stmts:(recovering_declaration() / unmatched_brace())* _ ![_] { stmts.into_iter().flatten().collect() }
+
+        // A declaration which fails to parse is skipped up to the next statement boundary: after a
+        // `;`, or before a keyword starting a statement or a `}` closing the enclosing block.
+        // Braces are skipped in pairs, so a block is skipped as a whole.
+        rule recovering_declaration() -> Option<Stmt> = stmt:declaration() { Some(stmt) } / recover_to_sync() { None }
+        rule recover_to_sync() = _ start:position!() !("}" / ![_]) skipped() (!sync() skipped())* (_ ";")? _ { recovery.skip(start) }
+        rule sync() = _ (";" / "}" / ![_] / SYNC_KEYWORD())
+        rule skipped() = _ (STRING() / "\"" [_]* / "{" (!(_ "}") skipped())* (_ "}")? / IDENTIFIER_CHAR()+ / [_])
+        rule unmatched_brace() -> Option<Stmt> = _ start:position!() "}" _ { recovery.skip(start); None }
+
+        pub rule declaration() -> Stmt = class_decl() / fun_decl() / var_decl() / statement()
+
+        rule class_decl() -> Stmt = _ CLASS() _ name:spanned(<name()>) _ superclass:("<" superclass:variable() { superclass })? _ "{" _ methods:(method:function() _ { Rc::new(method) })* _ "}" _ {
+            Stmt::Class(Class::new(name.0, superclass, methods, name.1))
+        }
+
+        rule fun_decl() -> Stmt = _ FUN() _ function:function() { Stmt::Function(Rc::new(function)) }
 
+        rule function() -> Function = name:spanned(<name()>) _ "(" _ params:parameters() _ ")" _ body:block_body() { Function::new(name.0, params, body, name.1) }
+        rule parameters() -> Vec<Symbol> = name() ** (_ "," _)
 
-        pub rule program() -> Vec<Stmt> = stmt:statement()* ![_] { stmt }
+        rule var_decl() -> Stmt = _ VAR() _ name:spanned(<name()>) _ init:("=" _ expr:expression() { expr })? _ ";" _ { Stmt::Var(Var::new(name.0, init, name.1)) }
 
-        pub rule statement() -> Stmt = expr_stmt() / print_stmt()
+        pub rule statement() -> Stmt = expr_stmt() / for_stmt() / if_stmt() / print_stmt() / return_stmt() / while_stmt() / block()
 
         rule expr_stmt() -> Stmt = _ expr:expression() _ ";" _ { Stmt::Expression(expr) }
 
-        rule print_stmt() -> Stmt = _ "print" _ expr:expression() _ ";" _ { Stmt::Print(expr) }
+        // The optional `else` is matched greedily, binding it to the nearest `if`
+        rule if_stmt() -> Stmt = _ IF() _ "(" _ condition:expression() _ ")" _ then_branch:statement() else_branch:(_ ELSE() _ stmt:statement() { stmt })? _ {
+            Stmt::If(If::new(condition, then_branch, else_branch))
+        }
+
+        rule while_stmt() -> Stmt = _ WHILE() _ "(" _ condition:expression() _ ")" _ body:statement() { Stmt::While(While::new(condition, body)) }
+
+        // For loops are desugared into while loops
+        rule for_stmt() -> Stmt = _ start:position!() FOR() end:position!() _ "(" _
+            initializer:(stmt:var_decl() { Some(stmt) } / stmt:expr_stmt() { Some(stmt) } / ";" { None }) _
+            condition:expression()? _ ";" _
+            increment:expression()? _ ")" _ body:statement() { desugar_for(initializer, condition, increment, body, Span::new(start, end)) }
+
+        rule print_stmt() -> Stmt = _ PRINT() _ expr:expression() _ ";" _ { Stmt::Print(expr) }
+
+        rule return_stmt() -> Stmt = _ start:position!() RETURN() _ value:expression()? _ ";" end:position!() _ { Stmt::Return(Return::new(value, Span::new(start, end))) }
+
+        rule block() -> Stmt = stmts:block_body() { Stmt::Block(stmts) }
+        rule block_body() -> Vec<Stmt> = _ "{" _ stmts:recovering_declaration()* _ "}" _ { stmts.into_iter().flatten().collect() }
+
+        pub rule expression() -> Expr = assignment()
 
-        pub rule expression() -> Expr = equality()
-        // pub rule expression() -> Expr = unary()
+        // The target is parsed as any expression, so an invalid target is reported at its span
+        rule assignment() -> Expr = target:logic_or() value:(_ "=" _ value:assignment() { value })? {
+            match value {
+                Some(value) => assign(target, value, recovery),
+                None => target,
+            }
+        }
 
-        // pub rule assignment() = ( call() "." )? IDENTIFIER() "=" assignment() / logic_or()
+        rule logic_or() -> Expr = left:logic_and() right:logic_or_pure()* { if right.is_empty() {left} else {flatten_logical(left, right)} }
+        rule logic_or_pure() -> (LogicalOp, Expr) = op:OR() expr:logic_and() { (op, expr) }
+
+        rule logic_and() -> Expr = left:equality() right:logic_and_pure()* { if right.is_empty() {left} else {flatten_logical(left, right)} }
+        rule logic_and_pure() -> (LogicalOp, Expr) = op:AND() expr:equality() { (op, expr) }
 
-        // pub rule logic_or() = logic_and() ( "or" logic_and() )*
-        // pub rule logic_and() = equality() ( "and" equality() )*
         rule equality() -> Expr = left:comparison() right:equality_pure()* { if right.is_empty() {left} else {flatten_binary(left,right)} }
-        rule equality_pure() -> (BinaryOp, Expr) = op:(EQ() / NE()) expr:comparison() { (op, expr) }
+        rule equality_pure() -> (BinaryOp, Span, Expr) = _ op:spanned(<EQ() / NE()>) _ expr:comparison() { (op.0, op.1, expr) }
 
 
         rule comparison() -> Expr = left:term() right:comparison_pure()* { if right.is_empty() {left} else {flatten_binary(left,right)} }
-        rule comparison_pure() -> (BinaryOp, Expr) = op:(LE() / GE() / GT() / LT()) expr:term() { (op, expr) }
+        rule comparison_pure() -> (BinaryOp, Span, Expr) = _ op:spanned(<LE() / GE() / GT() / LT()>) _ expr:term() { (op.0, op.1, expr) }
 
         rule term() -> Expr = left:factor() right:term_pure()*  { if right.is_empty() {left} else {flatten_binary(left, right)} }
-        rule term_pure() -> (BinaryOp, Expr) = op:(ADD() / SUB()) expr:factor() { (op, expr) }
+        rule term_pure() -> (BinaryOp, Span, Expr) = _ op:spanned(<ADD() / SUB()>) _ expr:factor() { (op.0, op.1, expr) }
 
         rule factor() -> Expr = left:unary() right:factor_pure()* { if right.is_empty() { left } else { flatten_binary(left, right) } }
-        rule factor_pure() -> (BinaryOp, Expr) = op:(DIV() / MUL()) expr:unary() { (op, expr) }
+        rule factor_pure() -> (BinaryOp, Span, Expr) = _ op:spanned(<DIV() / MUL()>) _ expr:unary() { (op.0, op.1, expr) }
 
-        rule unary() -> Expr = unary_pure() / primary()
-        rule unary_pure() -> Expr = op:(NOT() / NEG()) expr:unary() { Expr::Unary(Unary::new(expr, op))}
+        rule unary() -> Expr = unary_pure() / call()
+        rule unary_pure() -> Expr = _ op:spanned(<NOT() / NEG()>) _ expr:unary() { Expr::Unary(Unary::new(expr, op.0, op.1))}
 
-        // pub rule call() = primary() ( "(" arguments()? ")" / "." IDENTIFIER() )*
-        rule primary() -> Expr = literal() / variable() / brackets()
-                             // / "super" "." IDENTIFIER()
+        rule call() -> Expr = callee:primary() postfix:(call_pure() / get_pure())* {
+            postfix.into_iter().fold(callee, |callee, postfix| match postfix {
+                Postfix::Call(arguments, end) => {
+                    let span = Span::new(callee.span().start, end);
+                    Expr::Call(Call::new(callee, arguments, span))
+                }
+                Postfix::Get(name, end) => {
+                    let span = Span::new(callee.span().start, end);
+                    Expr::Get(Get::new(callee, name, span))
+                }
+            })
+        }
+        rule call_pure() -> Postfix = _ "(" _ args:arguments() _ ")" end:position!() _ { Postfix::Call(args, end) }
+        rule get_pure() -> Postfix = _ "." _ name:name() end:position!() _ { Postfix::Get(name, end) }
+        rule arguments() -> Vec<Expr> = expression() ** (_ "," _)
+        rule primary() -> Expr = literal() / this() / super_method() / variable() / brackets()
 
 
         rule literal() -> Expr = literal:(TRUE_LITERAL() / FALSE_LITERAL() / NUMBER_LITERAL() / STRING_LITERAL() / NIL_LITERAL()) { Expr::Literal(literal) }
-        rule variable() -> Expr = _ ident:$IDENTIFIER() _ { Expr::Variable(Variable::new(ident.to_string())) }
-        rule brackets() -> Expr = _ "(" _ expr:expression() _ ")" _ { expr }
-
-        // pub rule function() = IDENTIFIER() "(" parameters? ")" block()
-        // parameters     → IDENTIFIER ( "," IDENTIFIER )*
-        // rule arguments() -> Vec<Expr> = expression() ** ","
-
-        // rule THIS() -> Expr = "this" { Expr::This }
-        rule NUMBER_LITERAL() -> Literal = _ num:NUMBER() _ { Literal::new(LiteralValue::Number(num)) }
-        rule STRING_LITERAL() -> Literal = _ string:STRING() _ { Literal::new(LiteralValue::String(string)) }
-        rule TRUE_LITERAL() -> Literal = _ "true" _ { Literal::new(LiteralValue::Bool(true)) }
-        rule FALSE_LITERAL() -> Literal = _ "false" _ { Literal::new(LiteralValue::Bool(true)) }
-        rule NIL_LITERAL() -> Literal = _ "nil" _  { Literal::new(LiteralValue::Nil) }
-
-        rule NEG() -> UnaryOp = _ "-" _ { UnaryOp::Neg }
-        rule NOT() -> UnaryOp = _ "!" _ { UnaryOp::Not }
-        rule LT() -> BinaryOp = _ "<" _ { BinaryOp::Less }
-        rule LE() -> BinaryOp = _ "<=" _ { BinaryOp::LessEqual }
-        rule GT() -> BinaryOp = _ ">" _ { BinaryOp::Greater }
-        rule GE() -> BinaryOp = _ ">=" _ { BinaryOp::GreaterEqual }
-        rule EQ() -> BinaryOp = _ "==" _ { BinaryOp::Equal }
-        rule NE() -> BinaryOp = _ "!=" _ { BinaryOp::NotEqual }
-        rule MUL() -> BinaryOp = _ "*" _ { BinaryOp::Mul }
-        rule DIV() -> BinaryOp = _ "/" _ { BinaryOp::Div }
-        rule ADD() -> BinaryOp = _ "+" _ { BinaryOp::Add }
-        rule SUB() -> BinaryOp = _ "-" _ { BinaryOp::Sub }
+        rule variable() -> Expr = _ name:spanned(<name()>) _ { Expr::Variable(Variable::new(name.0, name.1)) }
+        rule this() -> Expr = _ start:position!() THIS() end:position!() _ { Expr::This(This::new(Span::new(start, end))) }
+        rule super_method() -> Expr = _ start:position!() SUPER() _ "." _ method:name() end:position!() _ { Expr::Super(Super::new(method, Span::new(start, end))) }
+        rule brackets() -> Expr = _ start:position!() "(" _ expr:expression() _ ")" end:position!() _ { Expr::Gropuping(Grouping::new(expr, Span::new(start, end))) }
+
+        // Identifiers which are not reserved keywords
+        rule name() -> Symbol = !KEYWORD() name:$IDENTIFIER() { Symbol::intern(name) }
+
+        // Matches a rule, returning its value together with the span of source code it matched
+        rule spanned<T>(r: rule<T>) -> (T, Span) = start:position!() value:r() end:position!() { (value, Span::new(start, end)) }
+
+        rule NUMBER_LITERAL() -> Literal = _ start:position!() num:NUMBER() end:position!() _ { Literal::new(LiteralValue::Number(num), Span::new(start, end)) }
+        rule STRING_LITERAL() -> Literal = _ start:position!() string:STRING() end:position!() _ { Literal::new(LiteralValue::String(Symbol::intern(&string)), Span::new(start, end)) }
+        rule TRUE_LITERAL() -> Literal = _ start:position!() TRUE() end:position!() _ { Literal::new(LiteralValue::Bool(true), Span::new(start, end)) }
+        rule FALSE_LITERAL() -> Literal = _ start:position!() FALSE() end:position!() _ { Literal::new(LiteralValue::Bool(false), Span::new(start, end)) }
+        rule NIL_LITERAL() -> Literal = _ start:position!() NIL() end:position!() _ { Literal::new(LiteralValue::Nil, Span::new(start, end)) }
+
+        rule SYNC_KEYWORD() = CLASS() / FUN() / VAR() / FOR() / IF() / WHILE() / PRINT() / RETURN()
+        rule KEYWORD() = AND_KEYWORD() / CLASS() / ELSE() / FALSE() / FOR() / FUN() / IF() / NIL() / OR_KEYWORD() / PRINT() / RETURN() / SUPER() / THIS() / TRUE() / VAR() / WHILE()
+        rule AND_KEYWORD() = "and" !IDENTIFIER_CHAR()
+        rule CLASS() = "class" !IDENTIFIER_CHAR()
+        rule ELSE() = "else" !IDENTIFIER_CHAR()
+        rule FALSE() = "false" !IDENTIFIER_CHAR()
+        rule FOR() = "for" !IDENTIFIER_CHAR()
+        rule FUN() = "fun" !IDENTIFIER_CHAR()
+        rule IF() = "if" !IDENTIFIER_CHAR()
+        rule NIL() = "nil" !IDENTIFIER_CHAR()
+        rule OR_KEYWORD() = "or" !IDENTIFIER_CHAR()
+        rule PRINT() = "print" !IDENTIFIER_CHAR()
+        rule RETURN() = "return" !IDENTIFIER_CHAR()
+        rule SUPER() = "super" !IDENTIFIER_CHAR()
+        rule THIS() = "this" !IDENTIFIER_CHAR()
+        rule TRUE() = "true" !IDENTIFIER_CHAR()
+        rule VAR() = "var" !IDENTIFIER_CHAR()
+        rule WHILE() = "while" !IDENTIFIER_CHAR()
+
+        rule NEG() -> UnaryOp = "-" { UnaryOp::Neg }
+        rule NOT() -> UnaryOp = "!" { UnaryOp::Not }
+        rule LT() -> BinaryOp = "<" { BinaryOp::Less }
+        rule LE() -> BinaryOp = "<=" { BinaryOp::LessEqual }
+        rule GT() -> BinaryOp = ">" { BinaryOp::Greater }
+        rule GE() -> BinaryOp = ">=" { BinaryOp::GreaterEqual }
+        rule EQ() -> BinaryOp = "==" { BinaryOp::Equal }
+        rule NE() -> BinaryOp = "!=" { BinaryOp::NotEqual }
+        rule MUL() -> BinaryOp = "*" { BinaryOp::Mul }
+        rule DIV() -> BinaryOp = "/" { BinaryOp::Div }
+        rule ADD() -> BinaryOp = "+" { BinaryOp::Add }
+        rule SUB() -> BinaryOp = "-" { BinaryOp::Sub }
+        rule AND() -> LogicalOp = _ AND_KEYWORD() _ { LogicalOp::And }
+        rule OR() -> LogicalOp = _ OR_KEYWORD() _ { LogicalOp::Or }
 
         pub rule NUMBER() -> f64 = num:$(DIGIT()+ ( "." DIGIT()+)?) { num.parse().unwrap() }
         pub rule STRING() -> String = "\"" string:$([^'"']*) "\"" { String::from(string) }
-        rule IDENTIFIER() = quiet!{ALPHA() ( ALPHA() / DIGIT() )*} / expected!("Identifier")
+        rule IDENTIFIER() = quiet!{ALPHA() IDENTIFIER_CHAR()*} / expected!("Identifier")
+        rule IDENTIFIER_CHAR() = ALPHA() / DIGIT()
         rule ALPHA() = ['a'..='z' | 'A'..='Z' | '_']
         rule DIGIT() = quiet!{['0'..='9']} / expected!("Number")
 
         // Match whitespace and comments
-        rule _ = quiet!{"//"[^'\n']*['\n'] / [' ' | '\n' | '\r' |'\t']*}
+        rule _ = quiet!{("//" [^'\n']* / [' ' | '\n' | '\r' | '\t'])*}
+    }
+}
+
+/// Errors recorded while parsing without failing the parse.
+#[derive(Default)]
+pub struct Recovery {
+    // Errors found in source code which matched the grammar
+    errors: RefCell<Vec<Diagnostic>>,
+    // Offsets of the declarations which failed to parse and were skipped
+    skipped: RefCell<Vec<usize>>,
+}
+
+impl Recovery {
+    // Records an error found while parsing. Rules can be parsed more than once when the parser
+    // backtracks, so an error already recorded at the same span is not recorded again.
+    fn record(&self, error: Diagnostic) {
+        let mut errors = self.errors.borrow_mut();
+        if !errors
+            .iter()
+            .any(|recorded| recorded.span() == error.span() && recorded.kind() == error.kind())
+        {
+            errors.push(error);
+        }
+    }
+
+    // Records a declaration starting at an offset which failed to parse and was skipped
+    fn skip(&self, start: usize) {
+        let mut skipped = self.skipped.borrow_mut();
+        if !skipped.contains(&start) {
+            skipped.push(start);
+        }
     }
 }
 
-fn flatten_binary(left: Expr, mut expr_list: Vec<(BinaryOp, Expr)>) -> Expr {
-    let (op, right) = expr_list.pop().expect("Factors list should never be zero");
+/// Parses a program, recovering from syntax errors so every error in the program is reported.
+///
+/// When a declaration fails to parse, it is skipped up to the next statement boundary and parsing
+/// continues from there. The syntax error of a skipped declaration is found by parsing it again on
+/// its own, which is only done for the diagnostic.
+pub fn parse(source: &str) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    let recovery = Recovery::default();
+    let parsed = lox_parser::program(source, &recovery);
+    for start in recovery.skipped.take() {
+        if let Err(error) = lox_parser::declaration(&source[start..], &Recovery::default()) {
+            recovery.record(syntax_error(
+                source,
+                start + error.location.offset,
+                &error.expected,
+            ));
+        }
+    }
+    let mut errors = recovery.errors.into_inner();
+    if let Err(error) = &parsed {
+        errors.push(syntax_error(source, error.location.offset, &error.expected));
+    }
+    errors.sort_by_key(|error| error.span().start);
+    match parsed {
+        Ok(stmts) if errors.is_empty() => Ok(stmts),
+        _ => Err(errors),
+    }
+}
+
+// Returns the diagnostic for source code which does not match the grammar, spanning the token the
+// parser stopped at and noting the tokens it expected instead
+fn syntax_error(source: &str, start: usize, expected: &ExpectedSet) -> Diagnostic {
+    let rest = &source[start..];
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let (span, message) = match rest.chars().next() {
+        None => (
+            Span::new(start, start),
+            "unexpected end of file".to_string(),
+        ),
+        Some(c) => {
+            let len = match rest.find(|c| !is_word(c)) {
+                _ if !is_word(c) => c.len_utf8(),
+                Some(len) => len,
+                None => rest.len(),
+            };
+            let token = &rest[..len];
+            (
+                Span::new(start, start + len),
+                format!("unexpected [{token}]"),
+            )
+        }
+    };
+    Diagnostic::SyntaxError {
+        kind: ErrorKind::Syntax,
+        span,
+        message,
+        notes: vec![format!("expected {expected}")],
+    }
+}
+
+// Returns the assignment of a value to a target, recording an error if the target is neither a
+// variable nor a property
+fn assign(target: Expr, value: Expr, recovery: &Recovery) -> Expr {
+    match target {
+        Expr::Variable(var) => {
+            let span = var.span.to(value.span());
+            Expr::Assign(Assign::new(var.name, value, span))
+        }
+        Expr::Get(get) => {
+            let span = get.span.to(value.span());
+            let (object, name) = get.into_parts();
+            Expr::Set(Set::new(object, name, value, span))
+        }
+        target => {
+            recovery.record(Diagnostic::SyntaxError {
+                kind: ErrorKind::InvalidAssignment,
+                span: target.span(),
+                message: "invalid assignment target".to_string(),
+                notes: Vec::new(),
+            });
+            target
+        }
+    }
+}
+
+// Postfix operators of a call expression, with the end of their span
+enum Postfix {
+    Call(Vec<Expr>, usize),
+    Get(Symbol, usize),
+}
+
+fn flatten_binary(left: Expr, mut expr_list: Vec<(BinaryOp, Span, Expr)>) -> Expr {
+    let (op, op_span, right) = expr_list.pop().expect("Factors list should never be zero");
     let left_expr = if expr_list.is_empty() {
         left
     } else {
         flatten_binary(left, expr_list)
     };
-    Expr::Binary(Binary::new(left_expr, right, op))
+    Expr::Binary(Binary::new(left_expr, right, op, op_span))
+}
+
+fn flatten_logical(left: Expr, mut expr_list: Vec<(LogicalOp, Expr)>) -> Expr {
+    let (op, right) = expr_list.pop().expect("Operands list should never be zero");
+    let left_expr = if expr_list.is_empty() {
+        left
+    } else {
+        flatten_logical(left, expr_list)
+    };
+    Expr::Logical(Logical::new(left_expr, right, op))
+}
+
+fn desugar_for(
+    initializer: Option<Stmt>,
+    condition: Option<Expr>,
+    increment: Option<Expr>,
+    body: Stmt,
+    keyword: Span,
+) -> Stmt {
+    let body = match increment {
+        Some(increment) => Stmt::Block(vec![body, Stmt::Expression(increment)]),
+        None => body,
+    };
+    // A missing condition is always true, spanning the `for` keyword
+    let condition =
+        condition.unwrap_or_else(|| Expr::Literal(Literal::new(LiteralValue::Bool(true), keyword)));
+    let loop_stmt = Stmt::While(While::new(condition, body));
+    match initializer {
+        Some(initializer) => Stmt::Block(vec![initializer, loop_stmt]),
+        None => loop_stmt,
+    }
 }
 
 // TODO: Add tests for the rest of the parser
@@ -100,22 +350,248 @@ mod tests {
     #[test]
     fn string() {
         assert_eq!(
-            lox_parser::STRING("\"Hello World\""),
+            lox_parser::STRING("\"Hello World\"", &Recovery::default()),
             Ok("Hello World".to_string())
         );
-        assert!(lox_parser::STRING("Hello World").is_err());
-        assert!(lox_parser::STRING("Hello World\"").is_err());
-        assert!(lox_parser::STRING("\"Hello World").is_err());
+        assert!(lox_parser::STRING("Hello World", &Recovery::default()).is_err());
+        assert!(lox_parser::STRING("Hello World\"", &Recovery::default()).is_err());
+        assert!(lox_parser::STRING("\"Hello World", &Recovery::default()).is_err());
     }
 
     #[test]
     fn number() {
-        assert_eq!(lox_parser::NUMBER("1.2345"), Ok(1.2345));
-        assert_eq!(lox_parser::NUMBER("12345"), Ok(12345f64));
-        assert!(lox_parser::NUMBER("12345asdf").is_err());
-        assert!(lox_parser::NUMBER("123,45").is_err());
+        assert_eq!(
+            lox_parser::NUMBER("1.2345", &Recovery::default()),
+            Ok(1.2345)
+        );
+        assert_eq!(
+            lox_parser::NUMBER("12345", &Recovery::default()),
+            Ok(12345f64)
+        );
+        assert!(lox_parser::NUMBER("12345asdf", &Recovery::default()).is_err());
+        assert!(lox_parser::NUMBER("123,45", &Recovery::default()).is_err());
+    }
+
+    #[test]
+    fn variable() {
+        assert!(lox_parser::expression("foo", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("variable", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("var", &Recovery::default()).is_err());
+        assert!(lox_parser::expression("print", &Recovery::default()).is_err());
+    }
+
+    #[test]
+    fn logical() {
+        assert!(lox_parser::expression("a and b or c", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("a or b == c and !d", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("a = b or c", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("android or oregon", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("and", &Recovery::default()).is_err());
+        assert!(lox_parser::expression("a or", &Recovery::default()).is_err());
+        assert_eq!(invalid_targets("a and b = c;"), ["a and b"]);
+    }
+
+    #[test]
+    fn assignment() {
+        assert!(lox_parser::expression("a = 1", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("a = b = 1 + 2", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("a == 1", &Recovery::default()).is_ok());
+        assert_eq!(invalid_targets("1 = 2;"), ["1"]);
+        assert_eq!(invalid_targets("a + b = 2;"), ["a + b"]);
+        assert_eq!(invalid_targets("a = b + c = 2;"), ["b + c"]);
+    }
+
+    // Returns the source of every invalid assignment target reported when parsing a program
+    fn invalid_targets(source: &str) -> Vec<&str> {
+        match parse(source) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors
+                .iter()
+                .filter(|error| error.kind() == ErrorKind::InvalidAssignment)
+                .map(|error| &source[error.span().start..error.span().end])
+                .collect(),
+        }
     }
 
     #[test]
-    fn variable() {}
+    fn invalid_assignment() {
+        let source = "var a;\nprint 1 + 2 = 3;\na.b() = 1;\nprint a = 1;";
+        let Err(errors) = parse(source) else {
+            panic!("expected parse errors")
+        };
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| {
+                let span = error.span();
+                (error.to_string(), &source[span.start..span.end])
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    "error[E0003]: invalid assignment target".to_string(),
+                    "1 + 2"
+                ),
+                (
+                    "error[E0003]: invalid assignment target".to_string(),
+                    "a.b()"
+                ),
+            ]
+        );
+        assert_eq!(invalid_targets("(a) = 1;"), ["(a)"]);
+        assert_eq!(invalid_targets("(a.b) = 1;"), ["(a.b)"]);
+        assert_eq!(invalid_targets("f() = 1;"), ["f()"]);
+        assert_eq!(invalid_targets("a + b // c\n= 1;"), ["a + b"]);
+        assert_eq!(invalid_targets("a + b\n  = 1;"), ["a + b"]);
+        assert_eq!(invalid_targets("a = (b) = 1;"), ["(b)"]);
+        assert!(invalid_targets("(a).b = 1; a.b.c = 1;").is_empty());
+        let Err(errors) = parse("print 1 + ;") else {
+            panic!("expected a parse error")
+        };
+        assert!(!errors[0].message().contains("assignable"));
+    }
+
+    #[test]
+    fn block() {
+        assert!(lox_parser::statement("{}", &Recovery::default()).is_ok());
+        assert!(lox_parser::statement("{ var a = 1; { print a; } }", &Recovery::default()).is_ok());
+        assert!(lox_parser::statement("{ var a = 1;", &Recovery::default()).is_err());
+    }
+
+    #[test]
+    fn if_else() {
+        assert!(lox_parser::statement("if (a) print 1;", &Recovery::default()).is_ok());
+        assert!(
+            lox_parser::statement("if (a) print 1; else print 2;", &Recovery::default()).is_ok()
+        );
+        assert!(lox_parser::statement(
+            "if (a) if (b) print 1; else print 2;",
+            &Recovery::default()
+        )
+        .is_ok());
+        assert!(lox_parser::statement("if a print 1;", &Recovery::default()).is_err());
+        assert!(lox_parser::statement("if (a) else print 2;", &Recovery::default()).is_err());
+    }
+
+    #[test]
+    fn loops() {
+        assert!(lox_parser::statement("while (a) a = a - 1;", &Recovery::default()).is_ok());
+        assert!(lox_parser::statement(
+            "for (var i = 0; i < 10; i = i + 1) print i;",
+            &Recovery::default()
+        )
+        .is_ok());
+        assert!(lox_parser::statement("for (i = 0; i < 10;) {}", &Recovery::default()).is_ok());
+        assert!(lox_parser::statement("for (;;) {}", &Recovery::default()).is_ok());
+        assert!(lox_parser::statement("while a {}", &Recovery::default()).is_err());
+        assert!(lox_parser::statement("for (;) {}", &Recovery::default()).is_err());
+    }
+
+    #[test]
+    fn functions() {
+        assert!(lox_parser::declaration("fun f() {}", &Recovery::default()).is_ok());
+        assert!(
+            lox_parser::declaration("fun add(a, b) { return a + b; }", &Recovery::default())
+                .is_ok()
+        );
+        assert!(lox_parser::declaration("fun f() { return; }", &Recovery::default()).is_ok());
+        assert!(lox_parser::declaration("fun (a) {}", &Recovery::default()).is_err());
+        assert!(lox_parser::declaration("fun f(a,) {}", &Recovery::default()).is_err());
+        assert!(lox_parser::declaration("fun f(var) {}", &Recovery::default()).is_err());
+        assert!(lox_parser::declaration("fun f() print 1;", &Recovery::default()).is_err());
+    }
+
+    #[test]
+    fn calls() {
+        assert!(lox_parser::expression("f()", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("f(1, a + b)(2)", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("-f(1)", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("f(1,)", &Recovery::default()).is_err());
+        assert!(lox_parser::expression("f(1", &Recovery::default()).is_err());
+    }
+
+    #[test]
+    fn classes() {
+        assert!(lox_parser::declaration("class A {}", &Recovery::default()).is_ok());
+        assert!(lox_parser::declaration(
+            "class A { init(a) { this.a = a; } get() { return this.a; } }",
+            &Recovery::default()
+        )
+        .is_ok());
+        assert!(lox_parser::declaration("class A { fun f() {} }", &Recovery::default()).is_err());
+        assert!(lox_parser::declaration(
+            "class B < A { f() { return super.f(); } }",
+            &Recovery::default()
+        )
+        .is_ok());
+        assert!(lox_parser::declaration("class { }", &Recovery::default()).is_err());
+        assert!(lox_parser::declaration("class B < { }", &Recovery::default()).is_err());
+        assert!(lox_parser::declaration("class B < A() { }", &Recovery::default()).is_err());
+    }
+
+    #[test]
+    fn properties() {
+        assert!(lox_parser::expression("a.b.c", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("a.b(1).c = 2", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("this.a = this", &Recovery::default()).is_ok());
+        assert_eq!(invalid_targets("a.b() = 2;"), ["a.b()"]);
+        assert!(lox_parser::expression("a.", &Recovery::default()).is_err());
+        assert!(lox_parser::expression("a.class", &Recovery::default()).is_err());
+        assert!(lox_parser::expression("super.a(1)", &Recovery::default()).is_ok());
+        assert!(lox_parser::expression("super", &Recovery::default()).is_err());
+        assert_eq!(invalid_targets("super.a = 1;"), ["super.a"]);
+    }
+
+    #[test]
+    fn comments() {
+        assert!(parse("// one\n// two\nvar a; // three").is_ok());
+        assert!(parse("var a; /").is_err());
+    }
+
+    #[test]
+    fn recovery() {
+        let source = "var a = ;\nprint a;\nfun f( { print \"; var\"; }\nvar b = 1 print b;\n\
+            fun g() { var = 1; print 1; }\nprint;";
+        let lines: Vec<_> = match parse(source) {
+            Err(errors) => errors
+                .iter()
+                .map(|error| {
+                    assert_eq!(error.kind(), ErrorKind::Syntax);
+                    error.span().start_location(source).line
+                })
+                .collect(),
+            Ok(_) => panic!("expected parse errors"),
+        };
+        assert_eq!(lines, vec![1, 3, 4, 5, 6]);
+        assert_eq!(parse("var a; print a;").map(|stmts| stmts.len()), Ok(2));
+
+        // Every error in a block is reported, at its offset in the whole program
+        let source = "{ var = 1; print 1; print; }\n}\nvar a = 1 +;";
+        let Err(errors) = parse(source) else {
+            panic!("expected parse errors")
+        };
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.span(), error.message()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (Span::new(6, 7), "unexpected [=]".to_string()),
+                (Span::new(25, 26), "unexpected [;]".to_string()),
+                (Span::new(29, 30), "unexpected [}]".to_string()),
+                (Span::new(42, 43), "unexpected [;]".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn var_declaration() {
+        assert!(lox_parser::declaration("var a;", &Recovery::default()).is_ok());
+        assert!(lox_parser::declaration("var a = 1 + 2;", &Recovery::default()).is_ok());
+        assert!(lox_parser::declaration("var = 1;", &Recovery::default()).is_err());
+        assert!(lox_parser::declaration("var a = 1", &Recovery::default()).is_err());
+        assert!(lox_parser::declaration("var a = ;", &Recovery::default()).is_err());
+    }
 }
@@ -0,0 +1,251 @@
+//! Syntax tree printers.
+//!
+//! Parsed programs can be dumped as JSON, using the [`serde`] representation of the syntax tree,
+//! or as S-expressions, which are more compact and easier to read:
+//!
+//! ```text
+//! (var a (+ 1 (* 2 3)))
+//! (print a)
+//! ```
+
+use std::fmt::{self, Write};
+
+use crate::expression::{BinaryOp, Expr, LiteralValue, LogicalOp, UnaryOp};
+use crate::statement::{Function, Stmt};
+
+/// Format the syntax tree of a program is dumped in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AstFormat {
+    /// Pretty-printed JSON array of statements.
+    #[default]
+    Json,
+    /// One S-expression per statement per line.
+    Sexpr,
+}
+
+/// Returns the syntax tree of a list of statements in the given format.
+pub fn dump(stmts: &[Stmt], format: AstFormat) -> String {
+    match format {
+        AstFormat::Json => {
+            serde_json::to_string_pretty(stmts).expect("Syntax trees should serialize to JSON")
+        }
+        AstFormat::Sexpr => stmts.iter().map(|stmt| sexpr(stmt) + "\n").collect(),
+    }
+}
+
+/// Returns a statement as an S-expression.
+pub fn sexpr(stmt: &Stmt) -> String {
+    let mut out = String::new();
+    write_stmt(&mut out, stmt).expect("Writing to a string should not fail");
+    out
+}
+
+fn write_stmt(out: &mut String, stmt: &Stmt) -> fmt::Result {
+    match stmt {
+        Stmt::Block(stmts) => {
+            write!(out, "(block")?;
+            write_stmts(out, stmts)?;
+            write!(out, ")")
+        }
+        Stmt::Class(class) => {
+            write!(out, "(class {}", class.name)?;
+            if let Some(superclass) = &class.superclass {
+                write!(out, " (< ")?;
+                write_expr(out, superclass)?;
+                write!(out, ")")?;
+            }
+            for method in &class.methods {
+                write!(out, " ")?;
+                write_function(out, method)?;
+            }
+            write!(out, ")")
+        }
+        Stmt::Expression(expr) => write_expr(out, expr),
+        Stmt::Function(function) => write_function(out, function),
+        Stmt::If(stmt) => {
+            write!(out, "(if ")?;
+            write_expr(out, &stmt.condition)?;
+            write!(out, " ")?;
+            write_stmt(out, &stmt.then_branch)?;
+            if let Some(else_branch) = &stmt.else_branch {
+                write!(out, " ")?;
+                write_stmt(out, else_branch)?;
+            }
+            write!(out, ")")
+        }
+        Stmt::Print(expr) => {
+            write!(out, "(print ")?;
+            write_expr(out, expr)?;
+            write!(out, ")")
+        }
+        Stmt::Return(stmt) => {
+            write!(out, "(return")?;
+            if let Some(value) = &stmt.value {
+                write!(out, " ")?;
+                write_expr(out, value)?;
+            }
+            write!(out, ")")
+        }
+        Stmt::Var(stmt) => {
+            write!(out, "(var {}", stmt.name)?;
+            if let Some(initializer) = &stmt.initializer {
+                write!(out, " ")?;
+                write_expr(out, initializer)?;
+            }
+            write!(out, ")")
+        }
+        Stmt::While(stmt) => {
+            write!(out, "(while ")?;
+            write_expr(out, &stmt.condition)?;
+            write!(out, " ")?;
+            write_stmt(out, &stmt.body)?;
+            write!(out, ")")
+        }
+    }
+}
+
+// Writes statements separated by spaces, with a leading space
+fn write_stmts(out: &mut String, stmts: &[Stmt]) -> fmt::Result {
+    for stmt in stmts {
+        write!(out, " ")?;
+        write_stmt(out, stmt)?;
+    }
+    Ok(())
+}
+
+fn write_function(out: &mut String, function: &Function) -> fmt::Result {
+    write!(
+        out,
+        "(fun {} ({})",
+        function.name,
+        function
+            .params
+            .iter()
+            .map(|param| param.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    )?;
+    write_stmts(out, &function.body)?;
+    write!(out, ")")
+}
+
+fn write_expr(out: &mut String, expr: &Expr) -> fmt::Result {
+    match expr {
+        Expr::Assign(expr) => {
+            write!(out, "(= {} ", expr.name)?;
+            write_expr(out, &expr.value)?;
+            write!(out, ")")
+        }
+        Expr::Binary(expr) => {
+            let operator = match expr.operator {
+                BinaryOp::Less => "<",
+                BinaryOp::LessEqual => "<=",
+                BinaryOp::Greater => ">",
+                BinaryOp::GreaterEqual => ">=",
+                BinaryOp::Equal => "==",
+                BinaryOp::NotEqual => "!=",
+                BinaryOp::Mul => "*",
+                BinaryOp::Div => "/",
+                BinaryOp::Add => "+",
+                BinaryOp::Sub => "-",
+            };
+            write!(out, "({operator} ")?;
+            write_expr(out, &expr.left)?;
+            write!(out, " ")?;
+            write_expr(out, &expr.right)?;
+            write!(out, ")")
+        }
+        Expr::Call(expr) => {
+            write!(out, "(call ")?;
+            write_expr(out, &expr.callee)?;
+            for argument in &expr.arguments {
+                write!(out, " ")?;
+                write_expr(out, argument)?;
+            }
+            write!(out, ")")
+        }
+        Expr::Get(expr) => {
+            write!(out, "(. ")?;
+            write_expr(out, &expr.object)?;
+            write!(out, " {})", expr.name)
+        }
+        Expr::Gropuping(expr) => {
+            write!(out, "(group ")?;
+            write_expr(out, &expr.expression)?;
+            write!(out, ")")
+        }
+        Expr::Literal(literal) => match &literal.value {
+            LiteralValue::Bool(value) => write!(out, "{value}"),
+            LiteralValue::Nil => write!(out, "nil"),
+            LiteralValue::Number(value) => write!(out, "{value}"),
+            LiteralValue::String(value) => write!(out, "{value:?}"),
+        },
+        Expr::Logical(expr) => {
+            let operator = match expr.operator {
+                LogicalOp::And => "and",
+                LogicalOp::Or => "or",
+            };
+            write!(out, "({operator} ")?;
+            write_expr(out, &expr.left)?;
+            write!(out, " ")?;
+            write_expr(out, &expr.right)?;
+            write!(out, ")")
+        }
+        Expr::Set(expr) => {
+            write!(out, "(= (. ")?;
+            write_expr(out, &expr.object)?;
+            write!(out, " {}) ", expr.name)?;
+            write_expr(out, &expr.value)?;
+            write!(out, ")")
+        }
+        Expr::Super(expr) => write!(out, "(super {})", expr.method),
+        Expr::This(_) => write!(out, "this"),
+        Expr::Unary(expr) => {
+            let operator = match expr.operator {
+                UnaryOp::Not => "!",
+                UnaryOp::Neg => "-",
+            };
+            write!(out, "({operator} ")?;
+            write_expr(out, &expr.operand)?;
+            write!(out, ")")
+        }
+        Expr::Variable(expr) => write!(out, "{}", expr.name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peg_parser;
+
+    fn dump_source(source: &str) -> String {
+        dump(&peg_parser::parse(source).unwrap(), AstFormat::Sexpr)
+    }
+
+    #[test]
+    fn statements() {
+        assert_eq!(
+            dump_source("var a = 1 + 2 * -3; print a; if (a) { a = nil; } else print \"b\";"),
+            "(var a (+ 1 (* 2 (- 3))))\n(print a)\n(if a (block (= a nil)) (print \"b\"))\n"
+        );
+        assert_eq!(
+            dump_source("for (var i = 0; i < 2; i = i + 1) print i;"),
+            "(block (var i 0) (while (< i 2) (block (print i) (= i (+ i 1)))))\n"
+        );
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(
+            dump_source("class B < A { init(x) { this.x = x; return; } f() { return super.f(1) or !true; } }"),
+            "(class B (< A) (fun init (x) (= (. this x) x) (return)) \
+             (fun f () (return (or (call (super f) 1) (! true)))))\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let stmts = peg_parser::parse("print 1;").unwrap();
+        assert!(dump(&stmts, AstFormat::Json).starts_with("[\n  {\n    \"Print/v1\""));
+    }
+}
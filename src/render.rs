@@ -0,0 +1,390 @@
+//! Diagnostic rendering.
+//!
+//! Diagnostics are rendered in a human readable format, locating them in the source code with a
+//! snippet of every line they refer to and underlining the spans of source code they label:
+//!
+//! ```text
+//! error[E0202]: value [1] of type Number cannot be added to value [Nil]
+//!  --> script.lox:2:9
+//!   |
+//! 2 | print a + nil;
+//!   |         ^
+//! ```
+//!
+//! Diagnostics can also be rendered as JSON objects for tools, one object per line.
+
+use serde::Serialize;
+use std::io::{self, Write};
+
+use crate::span::{Location, Span};
+use crate::{Diagnostic, Severity};
+
+/// Diagnostic prepared for rendering.
+pub struct Report {
+    pub severity: Severity,
+    /// Code of the kind of the diagnostic.
+    pub code: Option<&'static str>,
+    pub message: String,
+    /// Label of the span the diagnostic is reported at.
+    pub primary: Label,
+    /// Labels of other spans related to the diagnostic.
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+/// Span of source code with an optional message, rendered under the span.
+pub struct Label {
+    pub span: Span,
+    pub message: Option<String>,
+}
+
+// Diagnostic rendered as a JSON object, without locations if the source code is unknown
+#[derive(Serialize)]
+struct JsonDiagnostic<'a> {
+    code: &'static str,
+    severity: Severity,
+    message: String,
+    file: &'a str,
+    start: Option<Location>,
+    end: Option<Location>,
+}
+
+// ANSI escape codes used when rendering in color
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+
+impl Report {
+    pub fn new(message: String, span: Span) -> Self {
+        Report {
+            severity: Severity::Error,
+            code: None,
+            message,
+            primary: Label::new(span, None),
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Sets the severity and the code of the report.
+    pub fn with_code(mut self, severity: Severity, code: &'static str) -> Self {
+        self.severity = severity;
+        self.code = Some(code);
+        self
+    }
+
+    /// Sets the message of the primary label.
+    pub fn with_label(mut self, message: String) -> Self {
+        self.primary.message = Some(message);
+        self
+    }
+
+    /// Adds a secondary label.
+    pub fn with_secondary(mut self, span: Span, message: String) -> Self {
+        self.secondary.push(Label::new(span, Some(message)));
+        self
+    }
+
+    /// Adds a note, rendered after the source snippet.
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    /// Renders the report for a file with the given name and source code, using ANSI colors if
+    /// `color` is set. If the source code is unknown, because it is empty, only the name of the
+    /// file is rendered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `out` fails.
+    pub fn render(
+        &self,
+        out: &mut impl Write,
+        file: &str,
+        source: &str,
+        color: bool,
+    ) -> io::Result<()> {
+        let paint = |style: &'static str| if color { style } else { "" };
+        let reset = paint(RESET);
+        let location = self.primary.span.start_location(source);
+        let severity_color = match self.severity {
+            Severity::Error => RED,
+        };
+        let code = self.code.map_or(String::new(), |code| format!("[{code}]"));
+        writeln!(
+            out,
+            "{}{}{code}{reset}{}: {}{reset}",
+            paint(severity_color),
+            self.severity,
+            paint(BOLD),
+            self.message
+        )?;
+
+        // Every label with whether it is the primary label, by line and then by column
+        let mut labels: Vec<_> = std::iter::once((&self.primary, true))
+            .chain(self.secondary.iter().map(|label| (label, false)))
+            .map(|(label, primary)| (label.span.start_location(source).line, label, primary))
+            .collect();
+        labels.sort_by_key(|&(line, label, _)| (line, label.span.start));
+        let gutter_width = labels.last().map_or(1, |(line, ..)| line.to_string().len());
+        let gutter = format!("{}{:gutter_width$} |{reset}", paint(BLUE), "");
+
+        if source.is_empty() {
+            writeln!(out, "{}{:gutter_width$}-->{reset} {file}", paint(BLUE), "")?;
+            return Ok(());
+        }
+        writeln!(
+            out,
+            "{}{:gutter_width$}-->{reset} {file}:{}:{}",
+            paint(BLUE),
+            "",
+            location.line,
+            location.column
+        )?;
+        writeln!(out, "{gutter}")?;
+        let mut lines: Vec<_> = labels.iter().map(|&(line, ..)| line).collect();
+        lines.dedup();
+        for line in lines {
+            let text = source.lines().nth(line - 1).unwrap_or("");
+            writeln!(out, "{}{line:>gutter_width$} |{reset} {text}", paint(BLUE))?;
+            for &(_, label, primary) in labels.iter().filter(|(l, ..)| *l == line) {
+                let (marker, style) = if primary {
+                    ('^', severity_color)
+                } else {
+                    ('-', BLUE)
+                };
+                let (indent, width) = underline(source, label.span, text);
+                let message = label.message.as_deref().unwrap_or("");
+                writeln!(
+                    out,
+                    "{gutter} {indent}{}{}{}{reset}",
+                    paint(style),
+                    marker.to_string().repeat(width),
+                    if message.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" {message}")
+                    }
+                )?;
+            }
+        }
+        for note in &self.notes {
+            writeln!(
+                out,
+                "{}{:gutter_width$} ={reset} {}note{reset}: {note}",
+                paint(BLUE),
+                "",
+                paint(BOLD)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Label {
+    pub fn new(span: Span, message: Option<String>) -> Self {
+        Label { span, message }
+    }
+}
+
+// Returns the indentation before the underline of a span on its first line, keeping tabs so it
+// lines up with the source, and the width of the underline. Spans continuing past the line are
+// underlined to its end, and empty spans are underlined with a single marker.
+fn underline(source: &str, span: Span, text: &str) -> (String, usize) {
+    let start = span.start_location(source);
+    let end = span.end_location(source);
+    let indent: String = text
+        .chars()
+        .chain(std::iter::repeat(' '))
+        .take(start.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let end_column = if end.line == start.line {
+        end.column
+    } else {
+        text.chars().count() + 1
+    };
+    (indent, end_column.saturating_sub(start.column).max(1))
+}
+
+/// Renders a diagnostic reported in a file with the given name and source code as a JSON object
+/// on a single line, with the code, severity and message of the diagnostic, the file and the
+/// start and end locations of its span. The locations are `null` if the source code is empty.
+///
+/// # Errors
+///
+/// Returns an error if writing to `out` fails.
+pub fn render_json(
+    diagnostic: &Diagnostic,
+    out: &mut impl Write,
+    file: &str,
+    source: &str,
+) -> io::Result<()> {
+    let span = Some(diagnostic.span()).filter(|_| !source.is_empty());
+    let json = JsonDiagnostic {
+        code: diagnostic.code(),
+        severity: diagnostic.severity(),
+        message: diagnostic.message(),
+        file,
+        start: span.map(|span| span.start_location(source)),
+        end: span.map(|span| span.end_location(source)),
+    };
+    serde_json::to_writer(&mut *out, &json)?;
+    writeln!(out)
+}
+
+impl From<&Diagnostic> for Report {
+    fn from(diagnostic: &Diagnostic) -> Self {
+        let report = Report::new(diagnostic.message(), diagnostic.span())
+            .with_code(diagnostic.severity(), diagnostic.code());
+        let report = diagnostic
+            .labels()
+            .iter()
+            .fold(report, |report, (span, message)| {
+                report.with_secondary(*span, message.clone())
+            });
+        diagnostic
+            .notes()
+            .iter()
+            .fold(report, |report, note| report.with_note(note.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(report: &Report, source: &str) -> String {
+        let mut out = Vec::new();
+        report.render(&mut out, "test.lox", source, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn primary() {
+        let source = "var a = 1;\nprint a + nil;\n";
+        let report = Report::new("cannot add".to_string(), Span::new(17, 24));
+        assert_eq!(
+            render(&report, source),
+            "error: cannot add\n \
+             --> test.lox:2:7\n  \
+             |\n\
+             2 | print a + nil;\n  \
+             |       ^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn labels_and_notes() {
+        let source = "{\n\tvar a;\n\tvar a;\n}";
+        let report = Report::new("redeclared".to_string(), Span::new(15, 16))
+            .with_label("declared again".to_string())
+            .with_secondary(Span::new(7, 8), "first declared".to_string())
+            .with_note("rename one of them".to_string());
+        assert_eq!(
+            render(&report, source),
+            "error: redeclared\n \
+             --> test.lox:3:6\n  \
+             |\n\
+             2 | \tvar a;\n  \
+             | \t    - first declared\n\
+             3 | \tvar a;\n  \
+             | \t    ^ declared again\n  \
+             = note: rename one of them\n"
+        );
+    }
+
+    #[test]
+    fn diagnostic() {
+        let diagnostic = Diagnostic::ResolveError {
+            kind: crate::ErrorKind::TopLevelReturn,
+            span: Span::new(0, 7),
+            message: "cannot return from top-level code".to_string(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        };
+        assert_eq!(
+            render(&Report::from(&diagnostic), "return;"),
+            "error[E0103]: cannot return from top-level code\n \
+             --> test.lox:1:1\n  \
+             |\n\
+             1 | return;\n  \
+             | ^^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn diagnostic_labels_and_notes() {
+        let render_errors = |source: &str| {
+            let errors = crate::run(source, &mut crate::environment::Environment::new());
+            errors
+                .unwrap_err()
+                .iter()
+                .map(|error| render(&Report::from(error), source))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            render_errors("{\n  var a = 1;\n  var a = 2;\n}"),
+            [
+                "error[E0102]: variable [a] is already declared in this scope\n \
+              --> test.lox:3:7\n  \
+              |\n\
+              2 |   var a = 1;\n  \
+              |       - [a] is first declared here\n\
+              3 |   var a = 2;\n  \
+              |       ^\n"
+            ]
+        );
+        assert_eq!(
+            render_errors("{ var a = a; }"),
+            [
+                "error[E0101]: cannot read local variable [a] in its own initializer\n \
+              --> test.lox:1:11\n  \
+              |\n\
+              1 | { var a = a; }\n  \
+              |       - [a] is declared here\n  \
+              |           ^\n  \
+              = note: [a] is only defined once its initializer is evaluated, rename it to read a \
+              variable of an enclosing scope\n"
+            ]
+        );
+        assert_eq!(
+            render_errors("fun f(a) {}\nf(1, 2);"),
+            ["error[E0204]: function [<fn f>] expected 1 arguments but got 2\n \
+              --> test.lox:2:1\n  \
+              |\n\
+              2 | f(1, 2);\n  \
+              | ^^^^^^^\n  \
+              = note: every parameter takes exactly one argument, there are no optional parameters\n"]
+        );
+    }
+
+    #[test]
+    fn json() {
+        let diagnostic = Diagnostic::LoxError {
+            kind: crate::ErrorKind::UndefinedVariable,
+            span: Span::new(15, 16),
+            message: "undefined variable [b]".to_string(),
+            notes: Vec::new(),
+        };
+        let mut out = Vec::new();
+        render_json(&diagnostic, &mut out, "test.lox", "print 1;\nprint b;").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"code\":\"E0201\",\"severity\":\"error\",\"message\":\"undefined variable [b]\",\
+             \"file\":\"test.lox\",\"start\":{\"line\":2,\"column\":7},\"end\":{\"line\":2,\"column\":8}}\n"
+        );
+    }
+
+    #[test]
+    fn color() {
+        let report = Report::new("oops".to_string(), Span::new(0, 1));
+        let mut out = Vec::new();
+        report.render(&mut out, "test.lox", "x", true).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("\x1b[1;31merror"));
+    }
+}
@@ -0,0 +1,431 @@
+//! Lox static resolver.
+//!
+//! The resolver is a semantic analysis pass run on a program after it is parsed and before it is
+//! executed. It binds every local variable expression to the number of scopes between the
+//! expression and the declaration of the variable, so the variable is looked up lexically at
+//! runtime. Variables which are not found in any local scope are treated as globals.
+//!
+//! Programs which are broken statically, such as ones returning from top-level code, are reported
+//! before any part of them is executed.
+
+use std::collections::HashMap;
+
+use crate::expression::Expr;
+use crate::span::Span;
+use crate::statement::{Function, Stmt};
+use crate::symbol::Symbol;
+use crate::{Diagnostic, ErrorKind};
+
+/// Resolves the variables of a program, returning every [`Diagnostic`] found if the program is
+/// invalid.
+pub fn resolve(stmts: &[Stmt]) -> Result<(), Vec<Diagnostic>> {
+    let mut resolver = Resolver::new();
+    resolver.resolve_stmts(stmts);
+    if resolver.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(resolver.errors)
+    }
+}
+
+// Kind of function body being resolved
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+// Kind of class body being resolved
+#[derive(Clone, Copy, PartialEq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+// Local variable declared in a scope
+struct Binding {
+    // Whether the initializer of the variable has been resolved
+    defined: bool,
+    // Span of the declaration, the name of the function for parameters
+    span: Span,
+    parameter: bool,
+}
+
+struct Resolver {
+    // Local scopes from outermost to innermost, mapping names to their bindings
+    scopes: Vec<HashMap<Symbol, Binding>>,
+    function: FunctionKind,
+    class: ClassKind,
+    errors: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn resolve_stmts(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.resolve_stmt(stmt);
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block(stmts) => {
+                self.begin_scope();
+                self.resolve_stmts(stmts);
+                self.end_scope();
+            }
+            Stmt::Class(class) => {
+                let enclosing_class = self.class;
+                self.class = ClassKind::Class;
+                self.declare(&class.name, class.span, false);
+                self.define(&class.name);
+                if let Some(superclass) = &class.superclass {
+                    if let Expr::Variable(var) = superclass {
+                        if var.name == class.name {
+                            self.error(
+                                ErrorKind::SelfInheritance,
+                                format!("class [{}] cannot inherit from itself", class.name),
+                                var.span,
+                            );
+                        }
+                    }
+                    self.class = ClassKind::Subclass;
+                    self.resolve_expr(superclass);
+                    self.begin_scope();
+                    self.define(&Symbol::super_());
+                }
+                self.begin_scope();
+                self.define(&Symbol::this());
+                for method in &class.methods {
+                    let kind = if method.name == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
+                    self.resolve_function(method, kind);
+                }
+                self.end_scope();
+                if class.superclass.is_some() {
+                    self.end_scope();
+                }
+                self.class = enclosing_class;
+            }
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.resolve_expr(expr),
+            Stmt::Function(function) => {
+                self.declare(&function.name, function.span, false);
+                self.define(&function.name);
+                self.resolve_function(function, FunctionKind::Function);
+            }
+            Stmt::If(stmt) => {
+                self.resolve_expr(&stmt.condition);
+                self.resolve_stmt(&stmt.then_branch);
+                if let Some(else_branch) = &stmt.else_branch {
+                    self.resolve_stmt(else_branch);
+                }
+            }
+            Stmt::Return(stmt) => {
+                if self.function == FunctionKind::None {
+                    self.error(
+                        ErrorKind::TopLevelReturn,
+                        "cannot return from top-level code".to_string(),
+                        stmt.span,
+                    );
+                }
+                if let Some(value) = &stmt.value {
+                    if self.function == FunctionKind::Initializer {
+                        self.error(
+                            ErrorKind::InitializerReturn,
+                            "cannot return a value from an initializer".to_string(),
+                            value.span(),
+                        );
+                    }
+                    self.resolve_expr(value);
+                }
+            }
+            Stmt::Var(stmt) => {
+                self.declare(&stmt.name, stmt.span, false);
+                if let Some(initializer) = &stmt.initializer {
+                    self.resolve_expr(initializer);
+                }
+                self.define(&stmt.name);
+            }
+            Stmt::While(stmt) => {
+                self.resolve_expr(&stmt.condition);
+                self.resolve_stmt(&stmt.body);
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign(expr) => {
+                self.resolve_expr(&expr.value);
+                expr.depth.set(self.resolve_local(&expr.name));
+            }
+            Expr::Binary(expr) => {
+                self.resolve_expr(&expr.left);
+                self.resolve_expr(&expr.right);
+            }
+            Expr::Call(expr) => {
+                self.resolve_expr(&expr.callee);
+                for argument in &expr.arguments {
+                    self.resolve_expr(argument);
+                }
+            }
+            Expr::Get(expr) => self.resolve_expr(&expr.object),
+            Expr::Gropuping(expr) => self.resolve_expr(&expr.expression),
+            Expr::Literal(_) => (),
+            Expr::Logical(expr) => {
+                self.resolve_expr(&expr.left);
+                self.resolve_expr(&expr.right);
+            }
+            Expr::Set(expr) => {
+                self.resolve_expr(&expr.value);
+                self.resolve_expr(&expr.object);
+            }
+            Expr::Super(expr) => match self.class {
+                ClassKind::None => self.error(
+                    ErrorKind::InvalidSuper,
+                    "cannot use [super] outside of a class".to_string(),
+                    expr.span,
+                ),
+                ClassKind::Class => self.error(
+                    ErrorKind::InvalidSuper,
+                    "cannot use [super] in a class with no superclass".to_string(),
+                    expr.span,
+                ),
+                ClassKind::Subclass => expr.depth.set(self.resolve_local(&Symbol::super_())),
+            },
+            Expr::This(expr) => {
+                if self.class == ClassKind::None {
+                    self.error(
+                        ErrorKind::InvalidThis,
+                        "cannot use [this] outside of a class".to_string(),
+                        expr.span,
+                    );
+                }
+                expr.depth.set(self.resolve_local(&Symbol::this()));
+            }
+            Expr::Unary(expr) => self.resolve_expr(&expr.operand),
+            Expr::Variable(expr) => {
+                let binding = self.scopes.last().and_then(|scope| scope.get(&expr.name));
+                if let Some(Binding {
+                    defined: false,
+                    span,
+                    ..
+                }) = binding
+                {
+                    self.errors.push(Diagnostic::ResolveError {
+                        kind: ErrorKind::OwnInitializer,
+                        span: expr.span,
+                        message: format!(
+                            "cannot read local variable [{}] in its own initializer",
+                            expr.name
+                        ),
+                        labels: vec![(*span, format!("[{}] is declared here", expr.name))],
+                        notes: vec![format!(
+                            "[{}] is only defined once its initializer is evaluated, rename it to \
+                             read a variable of an enclosing scope",
+                            expr.name
+                        )],
+                    });
+                }
+                expr.depth.set(self.resolve_local(&expr.name));
+            }
+        }
+    }
+
+    // Resolves the body of a function in a new scope containing its parameters
+    fn resolve_function(&mut self, function: &Function, kind: FunctionKind) {
+        let enclosing_function = self.function;
+        self.function = kind;
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param, function.span, true);
+            self.define(param);
+        }
+        self.resolve_stmts(&function.body);
+        self.end_scope();
+        self.function = enclosing_function;
+    }
+
+    // Returns the number of scopes between the innermost scope and the scope declaring a variable,
+    // `None` if the variable is not declared in any local scope
+    fn resolve_local(&self, name: &Symbol) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(name))
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    // Declares a variable in the innermost local scope, marking it as not yet defined
+    fn declare(&mut self, name: &Symbol, span: Span, parameter: bool) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        let binding = Binding {
+            defined: false,
+            span,
+            parameter,
+        };
+        let Some(previous) = scope.insert(name.clone(), binding) else {
+            return;
+        };
+        // Parameters of the same function share the span of its name, which is already labeled
+        let labels = if previous.span == span {
+            Vec::new()
+        } else if previous.parameter {
+            vec![(
+                previous.span,
+                format!("[{name}] is first declared as a parameter of this function"),
+            )]
+        } else {
+            vec![(previous.span, format!("[{name}] is first declared here"))]
+        };
+        self.errors.push(Diagnostic::ResolveError {
+            kind: ErrorKind::Redeclaration,
+            span,
+            message: format!("variable [{name}] is already declared in this scope"),
+            labels,
+            notes: Vec::new(),
+        });
+    }
+
+    // Marks a variable in the innermost local scope as defined, declaring it if it is bound by the
+    // interpreter itself, like `this`
+    fn define(&mut self, name: &Symbol) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope
+                .entry(name.clone())
+                .or_insert(Binding {
+                    defined: true,
+                    span: Span::default(),
+                    parameter: false,
+                })
+                .defined = true;
+        }
+    }
+
+    fn error(&mut self, kind: ErrorKind, message: String, span: Span) {
+        self.errors.push(Diagnostic::ResolveError {
+            kind,
+            span,
+            message,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peg_parser::parse;
+
+    fn resolve_source(source: &str) -> Result<(), Vec<Diagnostic>> {
+        resolve(&parse(source).unwrap())
+    }
+
+    #[test]
+    fn valid() {
+        assert!(resolve_source("var a = 1; { var b = a; var a = b; }").is_ok());
+        assert!(resolve_source("var a = 1; var a = 2;").is_ok());
+        assert!(resolve_source("fun f(a) { return a; }").is_ok());
+        assert!(resolve_source(
+            "class A { init() { return; } } class B < A { f() { return super.f(this); } }"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn own_initializer() {
+        assert!(resolve_source("{ var a = a; }").is_err());
+    }
+
+    #[test]
+    fn redeclaration() {
+        assert!(resolve_source("{ var a = 1; var a = 2; }").is_err());
+        assert!(resolve_source("fun f(a, a) {}").is_err());
+        assert!(resolve_source("fun f(a) { var a; }").is_err());
+    }
+
+    #[test]
+    fn invalid_return() {
+        assert!(resolve_source("return;").is_err());
+        assert!(resolve_source("{ return 1; }").is_err());
+        assert!(resolve_source("class A { init() { return 1; } }").is_err());
+    }
+
+    #[test]
+    fn invalid_this_and_super() {
+        assert!(resolve_source("print this;").is_err());
+        assert!(resolve_source("fun f() { return this; }").is_err());
+        assert!(resolve_source("super.f();").is_err());
+        assert!(resolve_source("class A { f() { super.f(); } }").is_err());
+        assert!(resolve_source("class A < A {}").is_err());
+    }
+
+    #[test]
+    fn all_errors() {
+        assert_eq!(
+            resolve_source("return; print this; { var a = a; }").map_err(|errors| errors.len()),
+            Err(3)
+        );
+    }
+
+    #[test]
+    fn depths() {
+        let stmts = parse("var a; { var b; { a; b; } }").unwrap();
+        assert!(resolve(&stmts).is_ok());
+        let Stmt::Block(outer) = &stmts[1] else {
+            panic!()
+        };
+        let Stmt::Block(inner) = &outer[1] else {
+            panic!()
+        };
+        let depth = |stmt: &Stmt| match stmt {
+            Stmt::Expression(Expr::Variable(var)) => var.depth.get(),
+            _ => panic!(),
+        };
+        assert_eq!(depth(&inner[0]), None);
+        assert_eq!(depth(&inner[1]), Some(1));
+    }
+
+    #[test]
+    fn this_and_super_depths() {
+        let stmts = parse("class A {} class B < A { f() { { super.f; this; } } }").unwrap();
+        assert!(resolve(&stmts).is_ok());
+        let Stmt::Class(class) = &stmts[1] else {
+            panic!()
+        };
+        let Stmt::Block(block) = &class.methods[0].body[0] else {
+            panic!()
+        };
+        let depth = |stmt: &Stmt| match stmt {
+            Stmt::Expression(Expr::Super(expr)) => expr.depth.get(),
+            Stmt::Expression(Expr::This(expr)) => expr.depth.get(),
+            _ => panic!(),
+        };
+        assert_eq!(depth(&block[0]), Some(3));
+        assert_eq!(depth(&block[1]), Some(2));
+    }
+}
@@ -0,0 +1,133 @@
+//! Source code spans.
+//!
+//! Every node of the syntax tree records the [`Span`] of source code it was parsed from, as byte
+//! offsets into the source. Spans are resolved into line and column numbers only when a
+//! diagnostic is reported.
+
+use serde::{Deserialize, Serialize};
+
+/// Range of bytes in the source code, from `start` (inclusive) to `end` (exclusive).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Index of the lines of source code, locating the line of many offsets faster than
+/// [`Location::of`].
+#[derive(Clone, Debug)]
+pub struct LineIndex {
+    // Offset of the start of each line
+    starts: Vec<usize>,
+}
+
+/// Line and column in the source code, both starting from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Returns the smallest span covering both spans.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /// Returns the location of the start of the span in the source code.
+    pub fn start_location(&self, source: &str) -> Location {
+        Location::of(source, self.start)
+    }
+
+    /// Returns the location of the end of the span in the source code.
+    pub fn end_location(&self, source: &str) -> Location {
+        Location::of(source, self.end)
+    }
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let starts = if source.is_empty() {
+            Vec::new()
+        } else {
+            std::iter::once(0)
+                .chain(source.match_indices('\n').map(|(newline, _)| newline + 1))
+                .collect()
+        };
+        LineIndex { starts }
+    }
+
+    /// Returns the line of a byte offset, starting from 1. `None` is returned if the source code is
+    /// empty, which is the case for programs loaded from a syntax tree.
+    pub fn line(&self, offset: usize) -> Option<usize> {
+        if self.starts.is_empty() {
+            return None;
+        }
+        Some(self.starts.partition_point(|start| *start <= offset))
+    }
+}
+
+impl Location {
+    /// Returns the location of a byte offset in the source code.
+    ///
+    /// Columns are counted in characters. Offsets past the end of the source are clamped to it.
+    pub fn of(source: &str, offset: usize) -> Self {
+        let mut offset = offset.min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        Location {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations() {
+        let source = "var a;\nprint a + b;\n";
+        let span = Span::new(17, 18);
+        assert_eq!(
+            span.start_location(source),
+            Location {
+                line: 2,
+                column: 11
+            }
+        );
+        assert_eq!(
+            span.end_location(source),
+            Location {
+                line: 2,
+                column: 12
+            }
+        );
+        assert_eq!(Location::of(source, 0), Location { line: 1, column: 1 });
+        assert_eq!(Location::of(source, 100), Location { line: 3, column: 1 });
+        assert_eq!(Location::of("é = 1", 2), Location { line: 1, column: 2 });
+    }
+
+    #[test]
+    fn line_index() {
+        let lines = LineIndex::new("var a;\nprint a + b;\n");
+        assert_eq!(lines.line(0), Some(1));
+        assert_eq!(lines.line(6), Some(1));
+        assert_eq!(lines.line(7), Some(2));
+        assert_eq!(lines.line(20), Some(3));
+        assert_eq!(LineIndex::new("").line(0), None);
+    }
+
+    #[test]
+    fn join() {
+        assert_eq!(Span::new(4, 6).to(Span::new(1, 2)), Span::new(1, 6));
+    }
+}
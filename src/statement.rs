@@ -2,38 +2,303 @@
 //!
 //! Lox statements are also modelled as a tree structure similarly to expressions.
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
 
-use crate::Diagnostic;
+use crate::class::LoxClass;
+use crate::environment::Environment;
+use crate::expression::LoxValue;
+use crate::function::LoxFunction;
+use crate::span::Span;
+use crate::symbol::Symbol;
+use crate::{Diagnostic, ErrorKind};
 
 use super::expression::Expr;
 
 /// Statement types.
-#[derive(Serialize)]
+///
+/// Like expressions, statements are serialized with versioned node type names, such as `Var/v1`,
+/// so a node can change its format under a new version.
+#[derive(Deserialize, Serialize)]
 pub enum Stmt {
+    #[serde(rename = "Block/v1")]
+    Block(Vec<Stmt>),
+    #[serde(rename = "Class/v1")]
+    Class(Class),
+    #[serde(rename = "Expression/v1")]
     Expression(Expr),
+    #[serde(rename = "Function/v1")]
+    Function(Rc<Function>),
+    #[serde(rename = "If/v1")]
+    If(If),
+    #[serde(rename = "Print/v1")]
     Print(Expr),
+    #[serde(rename = "Return/v1")]
+    Return(Return),
+    #[serde(rename = "Var/v1")]
+    Var(Var),
+    #[serde(rename = "While/v1")]
+    While(While),
+}
+
+/// Class declaration statement.
+#[derive(Deserialize, Serialize)]
+pub struct Class {
+    pub(crate) name: Symbol,
+    pub(crate) superclass: Option<Expr>,
+    pub(crate) methods: Vec<Rc<Function>>,
+    // Span of the declared name
+    #[serde(default)]
+    pub(crate) span: Span,
+}
+
+/// Function declaration statement.
+#[derive(Deserialize, Serialize)]
+pub struct Function {
+    pub(crate) name: Symbol,
+    pub(crate) params: Vec<Symbol>,
+    pub(crate) body: Vec<Stmt>,
+    // Span of the declared name
+    #[serde(default)]
+    pub(crate) span: Span,
+}
+
+/// Conditional statement.
+#[derive(Deserialize, Serialize)]
+pub struct If {
+    pub(crate) condition: Expr,
+    pub(crate) then_branch: Box<Stmt>,
+    pub(crate) else_branch: Option<Box<Stmt>>,
+}
+
+/// Return statement.
+#[derive(Deserialize, Serialize)]
+pub struct Return {
+    pub(crate) value: Option<Expr>,
+    #[serde(default)]
+    pub(crate) span: Span,
+}
+
+/// Variable declaration statement.
+#[derive(Deserialize, Serialize)]
+pub struct Var {
+    pub(crate) name: Symbol,
+    pub(crate) initializer: Option<Expr>,
+    // Span of the declared name
+    #[serde(default)]
+    pub(crate) span: Span,
+}
+
+/// Loop statement.
+#[derive(Deserialize, Serialize)]
+pub struct While {
+    pub(crate) condition: Expr,
+    pub(crate) body: Box<Stmt>,
+}
+
+/// Control flow resulting from executing a statement.
+#[derive(Debug, PartialEq)]
+pub enum Flow {
+    /// Execution continues with the next statement.
+    Normal,
+    /// Execution unwinds to the enclosing function call, returning a value.
+    Return(LoxValue),
 }
 
 impl Stmt {
-    /// Prints the statement tree to the standard output in JSON format using [`serde_json`].
-    ///
-    /// # Panics
-    /// Meant to be used for debugging purposes only.
-    /// Unintended side-effects may arise.
-    /// Refer to [`serde_json`] documentation for safe usage.
-    pub fn print(&self) {
-        let stmt_json = serde_json::to_string_pretty(self).unwrap();
-        println!("{}", stmt_json);
-    }
-
-    pub fn execute(&self) -> Result<(), Diagnostic> {
+    /// Executes a statement in an [`Environment`] and returns how execution should proceed,
+    /// [`Diagnostic`] is returned if there is an error. Garbage is collected before the statement
+    /// is executed if needed.
+    pub fn execute(&self, env: &mut Environment) -> Result<Flow, Diagnostic> {
+        env.collect_if_needed();
         match self {
-            Stmt::Expression(expr) => expr.eval().map(|_| Ok(()))?,
+            Stmt::Block(stmts) => {
+                env.push_scope();
+                let result = execute_block(stmts, env);
+                env.pop_scope();
+                result
+            }
+            Stmt::Class(stmt) => stmt.execute(env),
+            Stmt::Expression(expr) => expr.eval(env).map(|_| Flow::Normal),
+            Stmt::Function(stmt) => {
+                let function = LoxFunction::new(Rc::clone(stmt), env.capture(), false);
+                env.define(stmt.name.clone(), LoxValue::Function(Rc::new(function)));
+                Ok(Flow::Normal)
+            }
+            Stmt::If(stmt) => stmt.execute(env),
             Stmt::Print(expr) => {
-                println!("{}", expr.eval()?);
-                Ok(())
+                let value = expr.eval(env)?;
+                env.print(&value, expr.span())?;
+                Ok(Flow::Normal)
+            }
+            Stmt::Return(stmt) => stmt.execute(env),
+            Stmt::Var(stmt) => stmt.execute(env),
+            Stmt::While(stmt) => stmt.execute(env),
+        }
+    }
+}
+
+/// Executes a list of statements in the current scope, stopping early if one of them returns.
+pub fn execute_block(stmts: &[Stmt], env: &mut Environment) -> Result<Flow, Diagnostic> {
+    for stmt in stmts {
+        if let Flow::Return(value) = stmt.execute(env)? {
+            return Ok(Flow::Return(value));
+        }
+    }
+    Ok(Flow::Normal)
+}
+
+impl Class {
+    pub fn new(
+        name: Symbol,
+        superclass: Option<Expr>,
+        methods: Vec<Rc<Function>>,
+        span: Span,
+    ) -> Self {
+        Class {
+            name,
+            superclass,
+            methods,
+            span,
+        }
+    }
+
+    fn execute(&self, env: &mut Environment) -> Result<Flow, Diagnostic> {
+        let superclass = match &self.superclass {
+            Some(expr) => match expr.eval(env)? {
+                LoxValue::Class(superclass) => Some(superclass),
+                value => return Err(Class::invalid_superclass(&self.name, &value, expr.span())),
+            },
+            None => None,
+        };
+        // Methods of subclasses capture a scope where `super` refers to the superclass
+        if let Some(superclass) = &superclass {
+            env.push_scope();
+            env.define(Symbol::super_(), LoxValue::Class(Rc::clone(superclass)));
+        }
+        let methods: HashMap<_, _> = self
+            .methods
+            .iter()
+            .map(|method| {
+                let is_initializer = method.name == "init";
+                let function = LoxFunction::new(Rc::clone(method), env.capture(), is_initializer);
+                (method.name.clone(), Rc::new(function))
+            })
+            .collect();
+        if superclass.is_some() {
+            env.pop_scope();
+        }
+        let class = LoxClass::new(self.name.clone(), superclass, methods);
+        env.define(self.name.clone(), LoxValue::Class(Rc::new(class)));
+        Ok(Flow::Normal)
+    }
+
+    /// Returns the error for a class inheriting from a value which is not a class, reported at the
+    /// span of the superclass.
+    pub(crate) fn invalid_superclass(name: &str, value: &LoxValue, span: Span) -> Diagnostic {
+        Diagnostic::LoxError {
+            kind: ErrorKind::InvalidSuperclass,
+            span,
+            message: format!(
+                "class [{name}] cannot inherit from value [{value}] of type {}",
+                value.type_str()
+            ),
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl Function {
+    pub fn new(name: Symbol, params: Vec<Symbol>, body: Vec<Stmt>, span: Span) -> Self {
+        Function {
+            name,
+            params,
+            body,
+            span,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn params(&self) -> &[Symbol] {
+        &self.params
+    }
+
+    pub fn body(&self) -> &[Stmt] {
+        &self.body
+    }
+}
+
+impl If {
+    pub fn new(condition: Expr, then_branch: Stmt, else_branch: Option<Stmt>) -> Self {
+        If {
+            condition,
+            then_branch: Box::new(then_branch),
+            else_branch: else_branch.map(Box::new),
+        }
+    }
+
+    fn execute(&self, env: &mut Environment) -> Result<Flow, Diagnostic> {
+        if self.condition.eval(env)?.is_truthy() {
+            self.then_branch.execute(env)
+        } else if let Some(else_branch) = &self.else_branch {
+            else_branch.execute(env)
+        } else {
+            Ok(Flow::Normal)
+        }
+    }
+}
+
+impl Return {
+    pub fn new(value: Option<Expr>, span: Span) -> Self {
+        Return { value, span }
+    }
+
+    fn execute(&self, env: &mut Environment) -> Result<Flow, Diagnostic> {
+        let value = match &self.value {
+            Some(expr) => expr.eval(env)?,
+            None => LoxValue::Nil,
+        };
+        Ok(Flow::Return(value))
+    }
+}
+
+impl Var {
+    pub fn new(name: Symbol, initializer: Option<Expr>, span: Span) -> Self {
+        Var {
+            name,
+            initializer,
+            span,
+        }
+    }
+
+    fn execute(&self, env: &mut Environment) -> Result<Flow, Diagnostic> {
+        let value = match &self.initializer {
+            Some(expr) => expr.eval(env)?,
+            None => LoxValue::Nil,
+        };
+        env.define(self.name.clone(), value);
+        Ok(Flow::Normal)
+    }
+}
+
+impl While {
+    pub fn new(condition: Expr, body: Stmt) -> Self {
+        While {
+            condition,
+            body: Box::new(body),
+        }
+    }
+
+    fn execute(&self, env: &mut Environment) -> Result<Flow, Diagnostic> {
+        while self.condition.eval(env)?.is_truthy() {
+            if let Flow::Return(value) = self.body.execute(env)? {
+                return Ok(Flow::Return(value));
             }
         }
+        Ok(Flow::Normal)
     }
 }
@@ -0,0 +1,300 @@
+//! Interned strings.
+//!
+//! Identifiers and string literals are [`Symbol`]s, reference-counted handles to strings stored
+//! once in an interner shared by the thread. They are interned when the program is parsed.
+//! Interning a string returns the handle to the copy already stored if there is one, so two
+//! symbols are equal exactly when they point to the same string, and symbols are compared and
+//! hashed by address without reading the strings themselves.
+//!
+//! String values are [`LoxString`]s. Strings created at runtime, such as the result of a
+//! concatenation, are not interned. A string literal shares the string of its symbol.
+//!
+//! Strings referenced only by the interner are removed from it when the
+//! [garbage collector](crate::gc) runs.
+
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+thread_local! {
+    static INTERNER: RefCell<HashSet<Interned>> = RefCell::new(HashSet::new());
+    // Names bound by the interpreter itself, interned once
+    static THIS: Symbol = Symbol::intern("this");
+    static SUPER: Symbol = Symbol::intern("super");
+    static INIT: Symbol = Symbol::intern("init");
+}
+
+/// Handle to an interned string.
+#[derive(Clone)]
+pub struct Symbol(Rc<Box<str>>);
+
+/// String value, shared by every copy of the value.
+///
+/// The string is boxed so the handle is a thin pointer, which can be stored in a NaN-boxed
+/// [`Value`](crate::value::Value).
+#[derive(Clone)]
+pub struct LoxString(Rc<Box<str>>);
+
+// String stored in the interner, hashed and compared by contents so it can be looked up by string
+struct Interned(Rc<Box<str>>);
+
+impl Symbol {
+    /// Returns the symbol of a string, interning the string if it was not interned yet.
+    pub fn intern(string: &str) -> Symbol {
+        INTERNER.with_borrow_mut(|interner| match interner.get(string) {
+            Some(interned) => Symbol(Rc::clone(&interned.0)),
+            None => {
+                let interned = Rc::new(Box::from(string));
+                interner.insert(Interned(Rc::clone(&interned)));
+                Symbol(interned)
+            }
+        })
+    }
+
+    /// Returns the symbol of `this`, bound to the instance in the scope of a method.
+    pub fn this() -> Symbol {
+        THIS.with(Symbol::clone)
+    }
+
+    /// Returns the symbol of `super`, bound to the superclass in the scope of the methods of a
+    /// subclass.
+    pub fn super_() -> Symbol {
+        SUPER.with(Symbol::clone)
+    }
+
+    /// Returns the symbol of `init`, the name of initializers.
+    pub fn init() -> Symbol {
+        INIT.with(Symbol::clone)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the symbol as a string value sharing its string.
+    pub fn to_lox_string(&self) -> LoxString {
+        LoxString(Rc::clone(&self.0))
+    }
+}
+
+impl LoxString {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Converts the string to a pointer, which must be converted back with
+    /// [`LoxString::from_raw`] to release the string.
+    #[cfg(feature = "nan-boxing")]
+    pub(crate) fn into_raw(self) -> *const () {
+        Rc::into_raw(self.0).cast()
+    }
+
+    /// Takes a new reference to the string of a pointer returned by [`LoxString::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// The pointer must come from [`LoxString::into_raw`] and not be converted back yet.
+    #[cfg(feature = "nan-boxing")]
+    pub(crate) unsafe fn increment_strong_count(ptr: *const ()) {
+        Rc::increment_strong_count(ptr.cast::<Box<str>>());
+    }
+
+    /// Converts a pointer returned by [`LoxString::into_raw`] back to a string.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must come from [`LoxString::into_raw`] and be converted back at most once.
+    #[cfg(feature = "nan-boxing")]
+    pub(crate) unsafe fn from_raw(ptr: *const ()) -> LoxString {
+        LoxString(Rc::from_raw(ptr.cast()))
+    }
+}
+
+/// Removes the strings which are no longer referenced by any symbol from the interner, returning
+/// the number of strings still interned.
+pub(crate) fn prune() -> usize {
+    INTERNER.with_borrow_mut(|interner| {
+        interner.retain(|string| Rc::strong_count(&string.0) > 1);
+        interner.len()
+    })
+}
+
+// Interned strings are unique, so symbols are equal only if they refer to the same string
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).cast::<u8>().hash(state);
+    }
+}
+
+impl PartialEq for Interned {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Interned {}
+
+impl Hash for Interned {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl Borrow<str> for Interned {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(string: &str) -> Self {
+        Symbol::intern(string)
+    }
+}
+
+// Strings created at runtime are not interned, so strings are compared by contents unless they
+// share the same string
+impl PartialEq for LoxString {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Deref for LoxString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for LoxString {
+    fn from(string: &str) -> Self {
+        LoxString(Rc::new(Box::from(string)))
+    }
+}
+
+impl From<String> for LoxString {
+    fn from(string: String) -> Self {
+        LoxString(Rc::new(string.into_boxed_str()))
+    }
+}
+
+impl fmt::Display for LoxString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Debug for LoxString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl Serialize for LoxString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        Ok(Symbol::intern(&string))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interning() {
+        let a = Symbol::intern("symbol");
+        let b = Symbol::intern(&String::from("symbol"));
+        assert_eq!(a, b);
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+        assert_eq!(std::mem::size_of::<Symbol>(), std::mem::size_of::<usize>());
+        assert_ne!(a, Symbol::intern("other"));
+        assert_eq!(a.as_str(), "symbol");
+        assert_eq!(format!("{a:?}"), "\"symbol\"");
+    }
+
+    #[test]
+    fn values() {
+        let mut env = crate::environment::Environment::new();
+        crate::run(
+            r#"var a = "con"; a = a + "cat"; var b = a == "concat"; var c = a + "enated";"#,
+            &mut env,
+        )
+        .unwrap();
+        let a = env.get_global(&"a".into()).unwrap();
+        assert_eq!(a, crate::expression::LoxValue::String("concat".into()));
+        assert_eq!(env.get_global(&"b".into()).unwrap().to_string(), "true");
+        // Concatenations are not interned, literals share the string of their symbol
+        let c = env.get_global(&"c".into()).unwrap();
+        assert_eq!(c.to_string(), "concatenated");
+        INTERNER.with_borrow(|interner| assert!(!interner.contains("concatenated")));
+        let literal = Symbol::intern("literal");
+        assert!(Rc::ptr_eq(&literal.0, &literal.to_lox_string().0));
+    }
+
+    #[test]
+    fn pruning() {
+        let kept = Symbol::intern("kept");
+        drop(Symbol::intern("dropped"));
+        prune();
+        INTERNER.with_borrow(|interner| {
+            assert!(interner.contains("kept"));
+            assert!(!interner.contains("dropped"));
+        });
+        drop(kept);
+    }
+}
@@ -0,0 +1,300 @@
+//! Representation of the values on the stack of the virtual machine.
+//!
+//! The [virtual machine](crate::vm) stores values as [`Value`]s, which are converted from and to
+//! [`LoxValue`]s at the boundaries with the rest of the interpreter. Numbers, booleans and `nil` can
+//! be created and read without conversion, so arithmetic does not depend on the representation.
+//!
+//! By default a value is a [`LoxValue`]. With the `nan-boxing` feature, a value is 8 bytes: numbers
+//! are stored as themselves, while `nil`, booleans and pointers to objects are stored in the
+//! payload of quiet NaNs, which arithmetic never produces once NaN results are canonicalized.
+
+#[cfg(not(feature = "nan-boxing"))]
+pub use tagged::Value;
+
+#[cfg(feature = "nan-boxing")]
+pub use nan_boxed::Value;
+
+#[cfg(not(feature = "nan-boxing"))]
+mod tagged {
+    use crate::expression::LoxValue;
+
+    /// Value stored as a [`LoxValue`].
+    #[derive(Clone, Debug)]
+    pub struct Value(LoxValue);
+
+    impl Value {
+        pub fn nil() -> Self {
+            Value(LoxValue::Nil)
+        }
+
+        pub fn bool(value: bool) -> Self {
+            Value(LoxValue::Bool(value))
+        }
+
+        pub fn number(value: f64) -> Self {
+            Value(LoxValue::Number(value))
+        }
+
+        /// Returns the number stored in the value, `None` if it is not a number.
+        pub fn as_number(&self) -> Option<f64> {
+            match self.0 {
+                LoxValue::Number(value) => Some(value),
+                _ => None,
+            }
+        }
+
+        pub fn is_truthy(&self) -> bool {
+            self.0.is_truthy()
+        }
+
+        pub fn to_lox(&self) -> LoxValue {
+            self.0.clone()
+        }
+    }
+
+    impl From<LoxValue> for Value {
+        fn from(value: LoxValue) -> Self {
+            Value(value)
+        }
+    }
+
+    impl From<Value> for LoxValue {
+        fn from(value: Value) -> Self {
+            value.0
+        }
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+mod nan_boxed {
+    use std::fmt;
+    use std::marker::PhantomData;
+    use std::mem::ManuallyDrop;
+    use std::rc::Rc;
+
+    use crate::class::{LoxClass, LoxInstance};
+    use crate::expression::LoxValue;
+    use crate::function::LoxFunction;
+    use crate::symbol::LoxString;
+
+    // Bits set in every quiet NaN used to store something else than a number
+    const QNAN: u64 = 0x7ffc_0000_0000_0000;
+    // NaN every NaN number is stored as, which is not a boxed value
+    const CANONICAL_NAN: u64 = 0x7ff8_0000_0000_0000;
+    const NIL: u64 = QNAN | 1;
+    const FALSE: u64 = QNAN | 2;
+    const TRUE: u64 = QNAN | 3;
+    // Objects set the sign bit, with the kind of object in the low bits of the aligned pointer
+    const OBJECT: u64 = 1 << 63 | QNAN;
+    const KIND: u64 = 0b11;
+    const STRING: u64 = 0;
+    const FUNCTION: u64 = 1;
+    const CLASS: u64 = 2;
+    const INSTANCE: u64 = 3;
+
+    /// Value stored in 8 bytes with NaN boxing.
+    ///
+    /// Objects are reference-counted pointers owned by the value, which are released when the
+    /// value is dropped.
+    pub struct Value {
+        bits: u64,
+        // Boxed pointers are not thread-safe
+        _marker: PhantomData<*const ()>,
+    }
+
+    impl Value {
+        const fn from_bits(bits: u64) -> Self {
+            Value {
+                bits,
+                _marker: PhantomData,
+            }
+        }
+
+        pub fn nil() -> Self {
+            Value::from_bits(NIL)
+        }
+
+        pub fn bool(value: bool) -> Self {
+            Value::from_bits(if value { TRUE } else { FALSE })
+        }
+
+        pub fn number(value: f64) -> Self {
+            if value.is_nan() {
+                Value::from_bits(CANONICAL_NAN)
+            } else {
+                Value::from_bits(value.to_bits())
+            }
+        }
+
+        /// Returns the number stored in the value, `None` if it is not a number.
+        pub fn as_number(&self) -> Option<f64> {
+            (self.bits & QNAN != QNAN).then(|| f64::from_bits(self.bits))
+        }
+
+        pub fn is_truthy(&self) -> bool {
+            self.bits != NIL && self.bits != FALSE
+        }
+
+        pub fn to_lox(&self) -> LoxValue {
+            match self.object() {
+                // The value keeps its reference to the object, the clone takes a new one
+                Some((kind, ptr)) => {
+                    LoxValue::clone(&ManuallyDrop::new(unsafe { object_from_raw(kind, ptr) }))
+                }
+                None => self.primitive(),
+            }
+        }
+
+        fn object(&self) -> Option<(u64, *const ())> {
+            let ptr = (self.bits & !(OBJECT | KIND)) as *const ();
+            (self.bits & OBJECT == OBJECT).then_some((self.bits & KIND, ptr))
+        }
+
+        fn primitive(&self) -> LoxValue {
+            match self.bits {
+                NIL => LoxValue::Nil,
+                FALSE => LoxValue::Bool(false),
+                TRUE => LoxValue::Bool(true),
+                bits => LoxValue::Number(f64::from_bits(bits)),
+            }
+        }
+
+        fn boxed(ptr: *const (), kind: u64) -> Self {
+            let address = ptr as u64;
+            assert!(
+                address & (OBJECT | KIND) == 0,
+                "Object pointers should fit in 48 bits and be aligned"
+            );
+            Value::from_bits(OBJECT | address | kind)
+        }
+    }
+
+    // Takes over the reference to an object owned by a boxed pointer
+    unsafe fn object_from_raw(kind: u64, ptr: *const ()) -> LoxValue {
+        match kind {
+            STRING => LoxValue::String(LoxString::from_raw(ptr)),
+            FUNCTION => LoxValue::Function(Rc::from_raw(ptr.cast())),
+            CLASS => LoxValue::Class(Rc::from_raw(ptr.cast())),
+            _ => LoxValue::Instance(Rc::from_raw(ptr.cast())),
+        }
+    }
+
+    // Takes a new reference to an object of a boxed pointer
+    unsafe fn increment_strong_count(kind: u64, ptr: *const ()) {
+        match kind {
+            STRING => LoxString::increment_strong_count(ptr),
+            FUNCTION => Rc::increment_strong_count(ptr.cast::<LoxFunction>()),
+            CLASS => Rc::increment_strong_count(ptr.cast::<LoxClass>()),
+            _ => Rc::increment_strong_count(ptr.cast::<LoxInstance>()),
+        }
+    }
+
+    impl From<LoxValue> for Value {
+        fn from(value: LoxValue) -> Self {
+            match value {
+                LoxValue::Nil => Value::nil(),
+                LoxValue::Bool(value) => Value::bool(value),
+                LoxValue::Number(value) => Value::number(value),
+                LoxValue::String(string) => Value::boxed(string.into_raw(), STRING),
+                LoxValue::Function(function) => {
+                    Value::boxed(Rc::into_raw(function).cast(), FUNCTION)
+                }
+                LoxValue::Class(class) => Value::boxed(Rc::into_raw(class).cast(), CLASS),
+                LoxValue::Instance(instance) => {
+                    Value::boxed(Rc::into_raw(instance).cast(), INSTANCE)
+                }
+            }
+        }
+    }
+
+    impl From<Value> for LoxValue {
+        fn from(value: Value) -> Self {
+            let value = ManuallyDrop::new(value);
+            match value.object() {
+                Some((kind, ptr)) => unsafe { object_from_raw(kind, ptr) },
+                None => value.primitive(),
+            }
+        }
+    }
+
+    // The copy owns a new reference to the object, taken without converting the value
+    impl Clone for Value {
+        fn clone(&self) -> Self {
+            if let Some((kind, ptr)) = self.object() {
+                unsafe { increment_strong_count(kind, ptr) };
+            }
+            Value::from_bits(self.bits)
+        }
+    }
+
+    impl Drop for Value {
+        fn drop(&mut self) {
+            if let Some((kind, ptr)) = self.object() {
+                drop(unsafe { object_from_raw(kind, ptr) });
+            }
+        }
+    }
+
+    impl fmt::Debug for Value {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt::Debug::fmt(&self.to_lox(), f)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::class::{LoxClass, LoxInstance};
+    use crate::expression::LoxValue;
+
+    #[test]
+    fn primitives() {
+        assert_eq!(Value::number(-1.5).as_number(), Some(-1.5));
+        assert!(Value::number(f64::NAN).as_number().unwrap().is_nan());
+        assert_eq!(
+            Value::number(f64::INFINITY).as_number(),
+            Some(f64::INFINITY)
+        );
+        assert_eq!(Value::nil().as_number(), None);
+        assert!(!Value::nil().is_truthy());
+        assert!(!Value::bool(false).is_truthy());
+        assert!(Value::bool(true).is_truthy());
+        assert!(Value::number(0.0).is_truthy());
+        assert_eq!(Value::bool(true).to_lox(), LoxValue::Bool(true));
+        assert_eq!(LoxValue::from(Value::nil()), LoxValue::Nil);
+        #[cfg(feature = "nan-boxing")]
+        assert_eq!(std::mem::size_of::<Value>(), 8);
+    }
+
+    #[test]
+    fn objects() {
+        let class = Rc::new(LoxClass::new("A".into(), None, Default::default()));
+        let instance = Rc::new(LoxInstance::new(Rc::clone(&class)));
+        let values = [
+            LoxValue::String("a".into()),
+            LoxValue::Class(Rc::clone(&class)),
+            LoxValue::Instance(Rc::clone(&instance)),
+        ];
+        for value in values {
+            let boxed = Value::from(value.clone());
+            assert_eq!(boxed.as_number(), None);
+            assert!(boxed.is_truthy());
+            let copy = boxed.clone();
+            assert_eq!(copy.to_lox(), value);
+            assert_eq!(LoxValue::from(boxed), value);
+        }
+        // A copy of a value takes its own reference to the object
+        let boxed = Value::from(LoxValue::Class(Rc::clone(&class)));
+        let copy = boxed.clone();
+        assert_eq!(Rc::strong_count(&class), 4);
+        drop(boxed);
+        assert_eq!(copy.to_lox(), LoxValue::Class(Rc::clone(&class)));
+        drop(copy);
+        // Every reference taken by the values was released
+        assert_eq!(Rc::strong_count(&class), 2);
+        assert_eq!(Rc::strong_count(&instance), 1);
+    }
+}
@@ -0,0 +1,742 @@
+//! Lox virtual machine.
+//!
+//! The virtual machine is an alternative backend to the tree-walking interpreter, executing
+//! programs [compiled](crate::compiler) to [bytecode](crate::chunk) on a stack of values. Each
+//! function call gets a frame whose local variables live in a window of the stack.
+//!
+//! Closures capture variables through upvalues, which refer to a stack slot while the variable is
+//! in scope and take over its value once the scope is exited. Globals are bound in the global
+//! scope of the [`Environment`], so they are shared with the rest of the interpreter.
+//!
+//! Operators and diagnostics are shared with the tree-walking interpreter, so both backends produce
+//! the same output and errors. Values on the stack use their own [representation](crate::value),
+//! with fast paths for operators applied to numbers.
+//!
+//! Garbage is collected before an instruction is executed, with the value stack, the closures of
+//! the frames and the open upvalues as roots in addition to the globals.
+//!
+//! Execution can be traced, printing the value stack and the
+//! [disassembled](crate::disassembler) instruction to the output of the environment before every
+//! instruction.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::class::{LoxClass, LoxInstance};
+use crate::disassembler;
+use crate::environment::{self, Environment, MAX_CALL_DEPTH};
+use crate::expression::{Assign, Binary, Call, Get, LoxValue, Set, Super, Unary, Variable};
+use crate::function::LoxFunction;
+use crate::span::{LineIndex, Span};
+use crate::statement::Class;
+use crate::symbol::Symbol;
+use crate::value::Value;
+use crate::Diagnostic;
+
+/// Compiled function with the variables it captured.
+pub struct Closure {
+    pub(crate) function: Rc<FunctionProto>,
+    pub(crate) upvalues: Vec<UpvalueRef>,
+}
+
+/// Shared reference to a captured variable.
+pub type UpvalueRef = Rc<RefCell<Upvalue>>;
+
+/// Variable captured by a closure.
+pub enum Upvalue {
+    /// Variable still in scope, stored in a slot of the value stack.
+    Open(usize),
+    /// Variable whose scope was exited, holding its last value.
+    Closed(LoxValue),
+}
+
+// Function call being executed
+struct CallFrame {
+    closure: Rc<Closure>,
+    // Offset of the next instruction in the chunk of the closure
+    ip: usize,
+    // Stack slot of the callee, followed by the arguments and local variables
+    base: usize,
+}
+
+/// Virtual machine executing compiled programs, with globals bound in an [`Environment`].
+pub struct Vm<'env> {
+    env: &'env mut Environment,
+    stack: Vec<Value>,
+    // Frames of the callers of the function currently executing
+    frames: Vec<CallFrame>,
+    // Upvalues referring to stack slots, ordered by slot
+    open_upvalues: Vec<UpvalueRef>,
+    // Lines of the source code of the program if execution is traced
+    trace: Option<LineIndex>,
+}
+
+impl<'env> Vm<'env> {
+    pub fn new(env: &'env mut Environment) -> Self {
+        Vm {
+            env,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            trace: None,
+        }
+    }
+
+    /// Traces execution, locating instructions in the source code the program was compiled from.
+    /// The trace is written to the output of the environment, [`ErrorKind::OutputFailed`] is
+    /// returned at the span of the instruction if it cannot be written.
+    pub fn with_trace(mut self, source: &str) -> Self {
+        self.trace = Some(LineIndex::new(source));
+        self
+    }
+
+    /// Executes a compiled script, returning [`Diagnostic`] if there is an error.
+    ///
+    /// If execution is aborted, the variables captured by closures are moved off the stack, so
+    /// closures stored in globals keep working.
+    pub fn run(&mut self, script: Rc<FunctionProto>) -> Result<(), Diagnostic> {
+        let closure = Rc::new(Closure {
+            function: script,
+            upvalues: Vec::new(),
+        });
+        let function = LoxFunction::compiled(Rc::clone(&closure));
+        self.stack
+            .push(Value::from(LoxValue::Function(Rc::new(function))));
+        let frame = CallFrame {
+            closure,
+            ip: 0,
+            base: 0,
+        };
+        self.execute(frame).map(drop)
+    }
+
+    /// Calls a compiled function with a list of arguments, returning its value, [`Diagnostic`] is
+    /// returned if there is an error. Used by the tree-walking interpreter to call the functions
+    /// created by a virtual machine.
+    pub(crate) fn call_compiled(
+        &mut self,
+        function: &LoxFunction,
+        arguments: Vec<LoxValue>,
+        span: Span,
+    ) -> Result<LoxValue, Diagnostic> {
+        // The slot of the callee is only read by methods, as the instance they are bound to
+        self.stack.push(Value::nil());
+        let count = arguments.len();
+        self.stack.extend(arguments.into_iter().map(Value::from));
+        match self.call_function(function, count, span)? {
+            Some(frame) => self.execute(frame).map(LoxValue::from),
+            None => Ok(self.pop().into()),
+        }
+    }
+
+    // Executes a frame until it returns, returning its value. If execution is aborted, the
+    // variables captured by closures are moved off the stack, so closures stored in globals keep
+    // working.
+    fn execute(&mut self, frame: CallFrame) -> Result<Value, Diagnostic> {
+        let result = self.execute_frame(frame);
+        if result.is_err() {
+            self.close_upvalues(0);
+            self.stack.clear();
+            self.frames.clear();
+        }
+        result
+    }
+
+    fn execute_frame(&mut self, mut frame: CallFrame) -> Result<Value, Diagnostic> {
+        loop {
+            if self.env.heap().should_collect() {
+                self.collect_garbage(&frame);
+            }
+            let chunk = &frame.closure.function.chunk;
+            let start = frame.ip;
+            let op = OpCode::from_byte(chunk.code[start]).expect("Chunks should contain opcodes");
+            let span = chunk.span(start);
+            if let Some(lines) = &self.trace {
+                let stack: Vec<_> = self.stack.iter().map(Value::to_lox).collect();
+                let output = self.env.output();
+                writeln!(output, "{}", disassembler::stack(&stack))
+                    .and_then(|()| {
+                        writeln!(output, "{}", disassembler::instruction(chunk, start, lines))
+                    })
+                    .map_err(|err| environment::output_failed(&err, span))?;
+            }
+            frame.ip += 1;
+            match op {
+                OpCode::Constant => {
+                    let index = read_u16(chunk, &mut frame.ip);
+                    let value = match &chunk.constants[usize::from(index)] {
+                        Constant::Number(value) => Value::number(*value),
+                        Constant::String(value) => {
+                            Value::from(LoxValue::String(value.to_lox_string()))
+                        }
+                        Constant::Function(_) => unreachable!("Functions are loaded as closures"),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::nil()),
+                OpCode::True => self.stack.push(Value::bool(true)),
+                OpCode::False => self.stack.push(Value::bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = frame.base + usize::from(read_u16(chunk, &mut frame.ip));
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = frame.base + usize::from(read_u16(chunk, &mut frame.ip));
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = read_name(chunk, &mut frame.ip);
+                    match self.env.get_global(name) {
+                        Some(value) => self.stack.push(Value::from(value)),
+                        None => return Err(Variable::undefined(name, span)),
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = read_name(chunk, &mut frame.ip);
+                    let value = self.pop();
+                    self.env.define_global(name.clone(), value.into());
+                }
+                OpCode::SetGlobal => {
+                    let name = read_name(chunk, &mut frame.ip);
+                    if !self.env.assign_global(name, self.peek(0).to_lox()) {
+                        return Err(Assign::undefined(name, span));
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = usize::from(read_u16(chunk, &mut frame.ip));
+                    let value = match &*frame.closure.upvalues[index].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => Value::from(value.clone()),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = usize::from(read_u16(chunk, &mut frame.ip));
+                    let value = self.peek(0).clone();
+                    match &mut *frame.closure.upvalues[index].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value.into(),
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = read_name(chunk, &mut frame.ip);
+                    let value = match self.pop().into() {
+                        LoxValue::Instance(instance) => LoxInstance::get(&instance, name, span)?,
+                        object => return Err(Get::no_properties(&object, span)),
+                    };
+                    self.stack.push(Value::from(value));
+                }
+                OpCode::CheckFields => {
+                    let object = self.peek(0).to_lox();
+                    if !matches!(object, LoxValue::Instance(_)) {
+                        return Err(Set::no_fields(&object, span));
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = read_name(chunk, &mut frame.ip);
+                    let value = self.pop();
+                    let LoxValue::Instance(instance) = self.pop().into() else {
+                        unreachable!("Fields are only set on checked instances");
+                    };
+                    instance.set(name.clone(), value.to_lox());
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = read_name(chunk, &mut frame.ip);
+                    let LoxValue::Class(superclass) = self.pop().into() else {
+                        unreachable!("Superclasses are checked when the subclass is created");
+                    };
+                    let instance = self.pop();
+                    match superclass.find_method(name) {
+                        Some(method) => {
+                            let method = method.bind(instance.into());
+                            self.stack
+                                .push(Value::from(LoxValue::Function(Rc::new(method))));
+                        }
+                        None => return Err(Super::undefined(name, &superclass, span)),
+                    }
+                }
+                OpCode::Equal => self.binary(|a, b| Value::bool(a == b), Binary::eq, span)?,
+                OpCode::NotEqual => self.binary(|a, b| Value::bool(a != b), Binary::ne, span)?,
+                OpCode::Greater => self.binary(|a, b| Value::bool(a > b), Binary::gt, span)?,
+                OpCode::GreaterEqual => {
+                    self.binary(|a, b| Value::bool(a >= b), Binary::ge, span)?;
+                }
+                OpCode::Less => self.binary(|a, b| Value::bool(a < b), Binary::lt, span)?,
+                OpCode::LessEqual => self.binary(|a, b| Value::bool(a <= b), Binary::le, span)?,
+                OpCode::Add => self.binary(|a, b| Value::number(a + b), Binary::add, span)?,
+                OpCode::Subtract => self.binary(|a, b| Value::number(a - b), Binary::sub, span)?,
+                OpCode::Multiply => self.binary(|a, b| Value::number(a * b), Binary::mul, span)?,
+                OpCode::Divide => self.binary(|a, b| Value::number(a / b), Binary::div, span)?,
+                OpCode::Not => {
+                    let operand = self.pop();
+                    self.stack.push(Value::from(Unary::not(operand.into())));
+                }
+                OpCode::Negate => {
+                    let operand = self.pop();
+                    let result = match operand.as_number() {
+                        Some(operand) => Value::number(-operand),
+                        None => Value::from(Unary::neg(operand.into(), span)?),
+                    };
+                    self.stack.push(result);
+                }
+                OpCode::Print => {
+                    let value = LoxValue::from(self.pop());
+                    self.env.print(&value, span)?;
+                }
+                OpCode::Jump => {
+                    let distance = read_u16(chunk, &mut frame.ip);
+                    frame.ip += usize::from(distance);
+                }
+                OpCode::JumpIfFalse => {
+                    let distance = read_u16(chunk, &mut frame.ip);
+                    if !self.peek(0).is_truthy() {
+                        frame.ip += usize::from(distance);
+                    }
+                }
+                OpCode::Loop => {
+                    let distance = read_u16(chunk, &mut frame.ip);
+                    frame.ip -= usize::from(distance);
+                }
+                OpCode::Call => {
+                    let arguments = usize::from(read_u16(chunk, &mut frame.ip));
+                    let callee_span = chunk.callee_span(start);
+                    // The frame is saved during the call, so it stays a root of the collector if
+                    // the callee is executed by the tree-walking interpreter
+                    self.frames.push(frame);
+                    let callee = self.call(arguments, span, callee_span);
+                    frame = self
+                        .frames
+                        .pop()
+                        .expect("The calling frame should be saved");
+                    if let Some(callee) = callee? {
+                        self.frames.push(std::mem::replace(&mut frame, callee));
+                    }
+                }
+                OpCode::Closure => {
+                    let index = read_u16(chunk, &mut frame.ip);
+                    let Constant::Function(function) = &chunk.constants[usize::from(index)] else {
+                        unreachable!("Closures are created from function constants");
+                    };
+                    let mut upvalues = Vec::with_capacity(function.upvalues);
+                    for _ in 0..function.upvalues {
+                        let is_local = chunk.code[frame.ip] == 1;
+                        frame.ip += 1;
+                        let index = usize::from(read_u16(chunk, &mut frame.ip));
+                        let upvalue = if is_local {
+                            self.capture_upvalue(frame.base + index)
+                        } else {
+                            Rc::clone(&frame.closure.upvalues[index])
+                        };
+                        upvalues.push(upvalue);
+                    }
+                    let closure = Closure {
+                        function: Rc::clone(function),
+                        upvalues,
+                    };
+                    let function = LoxFunction::compiled(Rc::new(closure));
+                    self.stack
+                        .push(Value::from(LoxValue::Function(Rc::new(function))));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    match self.frames.pop() {
+                        Some(caller) => {
+                            frame = caller;
+                            self.stack.push(result);
+                        }
+                        None => return Ok(result),
+                    }
+                }
+                OpCode::Class | OpCode::Subclass => {
+                    let name = read_name(chunk, &mut frame.ip);
+                    let count = usize::from(read_u16(chunk, &mut frame.ip));
+                    let methods: HashMap<_, _> = self
+                        .stack
+                        .split_off(self.stack.len() - count)
+                        .into_iter()
+                        .map(|method| match method.into() {
+                            LoxValue::Function(method) => (Symbol::intern(method.name()), method),
+                            _ => unreachable!("Methods are compiled to closures"),
+                        })
+                        .collect();
+                    let superclass = match (op, self.stack.last().map(Value::to_lox)) {
+                        (OpCode::Subclass, Some(LoxValue::Class(superclass))) => Some(superclass),
+                        (OpCode::Subclass, _) => unreachable!("Superclasses are checked"),
+                        _ => None,
+                    };
+                    let class = LoxClass::new(name.clone(), superclass, methods);
+                    self.stack
+                        .push(Value::from(LoxValue::Class(Rc::new(class))));
+                }
+                OpCode::Superclass => {
+                    let name = read_name(chunk, &mut frame.ip);
+                    let superclass = self.peek(0).to_lox();
+                    if !matches!(superclass, LoxValue::Class(_)) {
+                        return Err(Class::invalid_superclass(name, &superclass, span));
+                    }
+                }
+            }
+        }
+    }
+
+    // Calls the value below the arguments on top of the stack, returning the frame of the call
+    // if a function has to be executed, like the tree-walker's `Call`
+    fn call(
+        &mut self,
+        arguments: usize,
+        span: Span,
+        callee_span: Span,
+    ) -> Result<Option<CallFrame>, Diagnostic> {
+        let slot = self.stack.len() - arguments - 1;
+        match self.stack[slot].to_lox() {
+            LoxValue::Function(function) => self.call_function(&function, arguments, span),
+            LoxValue::Class(class) => {
+                let instance = Rc::new(LoxInstance::new(Rc::clone(&class)));
+                self.env.heap_mut().register_instance(&instance);
+                let instance = LoxValue::Instance(instance);
+                match class.find_method(&Symbol::init()) {
+                    Some(init) => {
+                        let init = init.bind(instance);
+                        self.call_function(&init, arguments, span)
+                    }
+                    None if arguments == 0 => {
+                        self.stack[slot] = Value::from(instance);
+                        Ok(None)
+                    }
+                    None => Err(class.arity_error(arguments, span)),
+                }
+            }
+            callee => Err(Call::not_callable(&callee, callee_span)),
+        }
+    }
+
+    // Calls a function with the arguments on top of the stack, returning the frame of the call if
+    // it is compiled. Functions of the tree-walking interpreter are called directly, replacing the
+    // callee and the arguments with their value.
+    fn call_function(
+        &mut self,
+        function: &LoxFunction,
+        arguments: usize,
+        span: Span,
+    ) -> Result<Option<CallFrame>, Diagnostic> {
+        let base = self.stack.len() - arguments - 1;
+        let Some((closure, receiver)) = function.compiled_closure() else {
+            let arguments = self.stack.split_off(base + 1);
+            let arguments = arguments.into_iter().map(LoxValue::from).collect();
+            self.pop();
+            let value = self.call_tree(function, arguments, span)?;
+            self.stack.push(Value::from(value));
+            return Ok(None);
+        };
+        function.check_arity(arguments, span)?;
+        // The frames include the caller while a call is made
+        if self.frames.len() + self.env.call_depth() > MAX_CALL_DEPTH {
+            return Err(environment::stack_overflow(span));
+        }
+        if let Some(receiver) = receiver {
+            self.stack[base] = Value::from(receiver.clone());
+        }
+        Ok(Some(CallFrame {
+            closure: Rc::clone(closure),
+            ip: 0,
+            base,
+        }))
+    }
+
+    // Calls a function of the tree-walking interpreter. The state of the virtual machine is held as
+    // roots of the garbage collector during the call, and its frames count towards the call depth.
+    fn call_tree(
+        &mut self,
+        function: &LoxFunction,
+        arguments: Vec<LoxValue>,
+        span: Span,
+    ) -> Result<LoxValue, Diagnostic> {
+        let frames = self.frames.len();
+        let mark = self.env.hold(&LoxValue::Nil);
+        for value in &self.stack {
+            self.env.hold(&value.to_lox());
+        }
+        for frame in &self.frames {
+            let closure = LoxFunction::compiled(Rc::clone(&frame.closure));
+            self.env.hold(&LoxValue::Function(Rc::new(closure)));
+        }
+        self.env.suspend_frames(frames);
+        let result = function.call(arguments, self.env, span);
+        self.env.resume_frames(frames);
+        self.env.release(mark);
+        result
+    }
+
+    // Applies a binary operator to the two values on top of the stack, with a fast path for
+    // numbers which must match the operator
+    fn binary(
+        &mut self,
+        numbers: fn(f64, f64) -> Value,
+        operator: fn(LoxValue, LoxValue, Span) -> Result<LoxValue, Diagnostic>,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let right = self.pop();
+        let left = self.pop();
+        let result = match (left.as_number(), right.as_number()) {
+            (Some(left), Some(right)) => numbers(left, right),
+            _ => Value::from(operator(left.into(), right.into(), span)?),
+        };
+        self.stack.push(result);
+        Ok(())
+    }
+
+    // Collects garbage reachable from neither the globals nor the state of the virtual machine
+    fn collect_garbage(&mut self, frame: &CallFrame) {
+        let Vm {
+            env,
+            stack,
+            frames,
+            open_upvalues,
+            ..
+        } = self;
+        env.collect_garbage(|tracer| {
+            for value in stack.iter() {
+                tracer.value(&value.to_lox());
+            }
+            for frame in frames.iter().chain([frame]) {
+                tracer.closure(&frame.closure);
+            }
+            for upvalue in open_upvalues.iter() {
+                tracer.upvalue(upvalue);
+            }
+        });
+    }
+
+    // Returns the upvalue referring to a stack slot, creating it if no closure captured the slot
+    fn capture_upvalue(&mut self, slot: usize) -> UpvalueRef {
+        let position = self
+            .open_upvalues
+            .iter()
+            .rposition(|upvalue| open_slot(upvalue) <= slot);
+        if let Some(position) = position {
+            if open_slot(&self.open_upvalues[position]) == slot {
+                return Rc::clone(&self.open_upvalues[position]);
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.env.heap_mut().register_upvalue(&upvalue);
+        let index = position.map_or(0, |position| position + 1);
+        self.open_upvalues.insert(index, Rc::clone(&upvalue));
+        upvalue
+    }
+
+    // Moves the values of the upvalues referring to a stack slot or above off the stack
+    fn close_upvalues(&mut self, from: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = open_slot(upvalue);
+            if slot < from {
+                break;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].to_lox());
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Value stack should not underflow")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - distance - 1]
+    }
+}
+
+// Reads the 16-bit operand at the instruction pointer, advancing it
+fn read_u16(chunk: &Chunk, ip: &mut usize) -> u16 {
+    let operand = chunk.read_u16(*ip);
+    *ip += 2;
+    operand
+}
+
+// Reads an operand referring to a name in the constant pool
+fn read_name<'chunk>(chunk: &'chunk Chunk, ip: &mut usize) -> &'chunk Symbol {
+    match &chunk.constants[usize::from(read_u16(chunk, ip))] {
+        Constant::String(name) => name,
+        _ => unreachable!("Names are string constants"),
+    }
+}
+
+fn open_slot(upvalue: &UpvalueRef) -> usize {
+    match *upvalue.borrow() {
+        Upvalue::Open(slot) => slot,
+        Upvalue::Closed(_) => unreachable!("Open upvalues should refer to a stack slot"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_with, Backend, ErrorKind, Options};
+
+    // Runs a program with both backends, checking that they report the same errors and bind the
+    // same values to a list of globals, which are returned
+    fn run_both(source: &str, globals: &[&str]) -> Result<Vec<String>, Vec<Diagnostic>> {
+        let [tree, vm] = [Backend::Tree, Backend::Vm].map(|backend| {
+            let mut env = Environment::new();
+            let options = Options {
+                backend,
+                ..Options::default()
+            };
+            run_with(source, &mut env, &options).map(|()| {
+                globals
+                    .iter()
+                    .map(|name| {
+                        env.get_global(&Symbol::intern(name))
+                            .map_or("undefined".into(), |v| v.to_string())
+                    })
+                    .collect::<Vec<_>>()
+            })
+        });
+        assert_eq!(tree, vm);
+        vm
+    }
+
+    #[test]
+    fn expressions() {
+        let source = r#"var a = 1 + 2 * -3; var b = "a" + "b"; var c = !nil and 1 <= 2;
+            var d = nil or "default"; var e = false and undefined; var f = 1 != 2;"#;
+        assert_eq!(
+            run_both(source, &["a", "b", "c", "d", "e", "f"]).unwrap(),
+            ["-5", "ab", "true", "default", "false", "true"]
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "var a = 0; var b = 0; var c;
+            while (a < 5) a = a + 1;
+            for (var i = 0; i < 5; i = i + 1) { var j = i; b = b + j; }
+            if (b > 5) c = 1; else c = 2;";
+        assert_eq!(
+            run_both(source, &["a", "b", "c", "i"]).unwrap(),
+            ["5", "10", "1", "undefined"]
+        );
+    }
+
+    #[test]
+    fn closures() {
+        let source = "fun counter() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }
+            var c = counter(); c(); var a = c();
+            var f; var g;
+            { var shared = 1; fun get() { return shared; } fun set() { shared = 2; } f = get; g = set; }
+            g(); var b = f();
+            fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } var d = fib(10);";
+        assert_eq!(
+            run_both(source, &["a", "b", "c", "d"]).unwrap(),
+            ["2", "2", "<fn inc>", "55"]
+        );
+    }
+
+    #[test]
+    fn classes() {
+        let source = "class A { init(x) { this.x = x; } get() { return this.x; } }
+            class B < A { init(x) { super.init(x * 2); return; } get() { return super.get() + 1; } }
+            var b = B(2); var a = b.get(); var m = b.get; var i = b.init(3) == b;
+            { class L { m() { return L; } } var l = L().m(); a = a + 0; }";
+        assert_eq!(
+            run_both(source, &["a", "b", "m", "i"]).unwrap(),
+            ["5", "<B instance>", "<fn get>", "true"]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let sources = [
+            "print 1 + nil;",
+            "print -\"a\";",
+            "print a;",
+            "a = 1;",
+            "nil();",
+            "fun f(a) {} f();",
+            "class A {} A(1);",
+            "class A {} A().x;",
+            "1.x = undefined;",
+            "var A = 1; class B < A {}",
+            "class A {} class B < A { m() { return super.m; } } B().m();",
+        ];
+        for source in sources {
+            assert!(run_both(source, &[]).is_err(), "{source}");
+        }
+        let errs = run_both("fun f() { f(); }\nf();", &[]).unwrap_err();
+        assert_eq!(errs[0].kind(), ErrorKind::StackOverflow);
+        assert_eq!(errs[0].span(), Span::new(10, 13));
+    }
+
+    #[test]
+    fn aborted_execution() {
+        let mut env = Environment::new();
+        let options = Options {
+            backend: Backend::Vm,
+            ..Options::default()
+        };
+        let source = "var g; { var a = 1; fun f() { a = a + 1; return a; } g = f; nil(); }";
+        assert!(run_with(source, &mut env, &options).is_err());
+        assert_eq!(run_with("var b = g();", &mut env, &options), Ok(()));
+        assert_eq!(env.get_global(&"b".into()), Some(LoxValue::Number(2.0)));
+    }
+
+    #[test]
+    fn mixed_backends() {
+        let [tree, vm] = [Backend::Tree, Backend::Vm].map(|backend| Options {
+            backend,
+            ..Options::default()
+        });
+        let mut env = Environment::new();
+        // Collecting garbage before every statement and instruction checks that the callers of
+        // the other backend stay reachable during calls
+        env.heap_mut().configure(0, 0);
+        let programs = [
+            (
+                &vm,
+                "fun counter() { var i = 0; fun count() { i = i + 1; return i; } return count; }
+                class A { init(x) { this.x = x; } get() { return this.x; } }",
+            ),
+            (
+                &tree,
+                "var c = counter(); c(); var a = A(c()); var b = a.get();
+                class B < A { get() { return super.get() * 10; } }
+                fun twice(f, x) { return f(f(x)); }",
+            ),
+            (&vm, "var d = B(b).get(); var e = twice(fun_add, 1);"),
+            (
+                &tree,
+                "fun f(n) { if (n == 0) return 0; return 1 + g(n - 1); }",
+            ),
+            (
+                &vm,
+                "fun g(n) { if (n == 0) return 0; return 1 + f(n - 1); } var h = f(100);",
+            ),
+        ];
+        assert_eq!(
+            run_with("fun fun_add(x) { return x + 1; }", &mut env, &vm),
+            Ok(())
+        );
+        for (options, source) in programs {
+            assert_eq!(run_with(source, &mut env, options), Ok(()), "{source}");
+        }
+        let globals = ["b", "d", "e", "h"].map(|name| env.get_global(&name.into()));
+        let numbers = [2.0, 20.0, 3.0, 100.0].map(|n| Some(LoxValue::Number(n)));
+        assert_eq!(globals, numbers);
+        let errs = run_with("f(1, 2);", &mut env, &vm).unwrap_err();
+        assert_eq!(errs[0].kind(), ErrorKind::ArityMismatch);
+        let errs = run_with("A();", &mut env, &tree).unwrap_err();
+        assert_eq!(errs[0].kind(), ErrorKind::ArityMismatch);
+    }
+}
@@ -0,0 +1,45 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Runs the interpreter on a script with arguments, returning its standard output
+fn lox(args: &[&str], source: &str) -> String {
+    let path: PathBuf = std::env::temp_dir().join(format!("rslox-cli-{}.lox", std::process::id()));
+    fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .args(args)
+        .arg(&path)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn optimizes_by_default() {
+    let source = "print 1 + 2;\n";
+    let folded = "\
+== script ==
+0000    1 Constant           0 3
+0003    | Print
+0004    | Nil
+0005    | Return
+";
+    let unfolded = "\
+== script ==
+0000    1 Constant           0 1
+0003    | Constant           1 2
+0006    | Add
+0007    | Print
+0008    | Nil
+0009    | Return
+";
+    assert_eq!(lox(&["--disassemble", "--no-exec"], source), folded);
+    assert_eq!(lox(&["-O1", "--disassemble", "--no-exec"], source), folded);
+    assert_eq!(
+        lox(&["-O0", "--disassemble", "--no-exec"], source),
+        unfolded
+    );
+    assert_eq!(lox(&["-O0", "--backend=vm"], source), "3\n");
+}